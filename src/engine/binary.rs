@@ -0,0 +1,97 @@
+use macroquad::math::Vec2;
+
+// Shared pieces of the binary save formats.
+// Every format starts with a four byte magic and a version byte, integers are LEB128 varints and floats are little endian.
+
+// Readers take every version up to this one, writers always write it.
+// 2 added the entity kinematic flag, 3 its rigid body coefficients, 4 the blocks each saved tree is made of, 5 entity handles in scenes
+pub const FORMAT_VERSION: u8 = 5;
+
+#[derive(Default)]
+pub struct ByteWriter(Vec<u8>);
+impl ByteWriter {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn header(&mut self, magic:&[u8; 4]) { self.versioned_header(magic, FORMAT_VERSION) }
+
+    /// For formats which change separately from the saves and keep their own version.
+    pub fn versioned_header(&mut self, magic:&[u8; 4], version:u8) {
+        self.0.extend_from_slice(magic);
+        self.0.push(version);
+    }
+
+    pub fn varint(&mut self, mut value:u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 { self.0.push(byte); break }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    pub fn f32(&mut self, value:f32) { self.0.extend_from_slice(&value.to_le_bytes()) }
+
+    pub fn vec2(&mut self, value:Vec2) {
+        self.f32(value.x);
+        self.f32(value.y);
+    }
+
+    /// Length prefixed, for nesting one format inside another.
+    pub fn bytes(&mut self, value:&[u8]) {
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> { self.0 }
+}
+
+// Every read returns None once the data runs out or stops making sense.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+impl<'a> ByteReader<'a> {
+    pub fn new(data:&'a [u8]) -> Self { Self { data, cursor: 0 } }
+
+    fn take(&mut self, length:usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.cursor .. self.cursor.checked_add(length)?)?;
+        self.cursor += length;
+        Some(bytes)
+    }
+
+    /// Consumes the header and returns its version, failing if the magic doesn't match or the version is newer than this build.
+    pub fn header(&mut self, magic:&[u8; 4]) -> Option<u8> { self.versioned_header(magic, FORMAT_VERSION) }
+
+    /// Same as header, for a format on its own version.
+    pub fn versioned_header(&mut self, magic:&[u8; 4], latest:u8) -> Option<u8> {
+        if self.take(4)? != magic { return None }
+        let version = self.take(1)?[0];
+        (1 ..= latest).contains(&version).then_some(version)
+    }
+
+    pub fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0 .. 64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 { return Some(value) }
+        }
+        None
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn vec2(&mut self) -> Option<Vec2> {
+        Some(Vec2::new(self.f32()?, self.f32()?))
+    }
+
+    pub fn bytes(&mut self) -> Option<&'a [u8]> {
+        let length = usize::try_from(self.varint()?).ok()?;
+        self.take(length)
+    }
+}
+
+/// Whether data starts with magic, used to tell binary saves apart from json ones.
+pub fn has_magic(data:&[u8], magic:&[u8; 4]) -> bool { data.starts_with(magic) }
@@ -1,39 +1,87 @@
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum CollisionType {
     Solid,  // index 1 or 3
     Air,    // index 0 or 2
     Void,   // No block, unspecified behavior
+    Sensor, // Lets everything through, but reports corners entering and leaving it
 }
 
+use std::collections::HashMap;
 use macroquad::color::*;
+use serde::{Serialize, Deserialize};
 use super::grid::partition::CellData;
+use super::grid::dag::Leaf;
+use super::binary::{ByteReader, ByteWriter};
+use super::world::Graph;
 
-#[derive(Debug)]
-struct Block {
+/// Blocks are matched by value when a save is loaded, so two blocks alike in every field are the same material.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, derive_new::new)]
+pub struct Block {
+    #[serde(with = "rgba")]
     color : Color,
-    collision_type : CollisionType
+    collision_type : CollisionType,
+    // Mass per unit of area, only solid blocks weigh anything
+    #[new(value = "1.")]
+    density : f32,
+}
+impl Block {
+    fn write(&self, writer:&mut ByteWriter) {
+        for channel in rgba::channels(self.color) { writer.f32(channel) }
+        writer.varint(self.collision_type as u64);
+        writer.f32(self.density);
+    }
+
+    fn read(reader:&mut ByteReader) -> Option<Self> {
+        let color = Color::new(reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+        let collision_type = match reader.varint()? {
+            0 => CollisionType::Solid,
+            1 => CollisionType::Air,
+            2 => CollisionType::Void,
+            3 => CollisionType::Sensor,
+            _ => return None,
+        };
+        Some(Self { color, collision_type, density: reader.f32()? })
+    }
+}
+
+// Color has no serde support of its own
+mod rgba {
+    use macroquad::color::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    pub fn channels(color:Color) -> [f32; 4] { [color.r, color.g, color.b, color.a] }
+    pub fn serialize<S: Serializer>(color:&Color, serializer:S) -> Result<S::Ok, S::Error> {
+        channels(*color).serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer:D) -> Result<Color, D::Error> {
+        let [r, g, b, a] = <[f32; 4]>::deserialize(deserializer)?;
+        Ok(Color::new(r, g, b, a))
+    }
 }
 
-pub struct BlockPalette([Block; 4]);
+/// Indexed by Leaf, so it has to grow alongside the graph's leaves.
+pub struct BlockPalette(Vec<Block>);
 impl Default for BlockPalette {
     fn default() -> Self {
-        Self ( [
+        Self ( vec![
                 Block {
                     color : BLANK,
-                    collision_type : CollisionType::Air
+                    collision_type : CollisionType::Air,
+                    density : 1.,
                 },
                 Block {
                     color : GREEN,
-                    collision_type : CollisionType::Solid
+                    collision_type : CollisionType::Solid,
+                    density : 1.,
                 },
                 Block {
                     color : BLUE,
-                    collision_type : CollisionType::Air
+                    collision_type : CollisionType::Air,
+                    density : 1.,
                 },
                 Block {
                     color : GRAY,
-                    collision_type : CollisionType::Solid
+                    collision_type : CollisionType::Solid,
+                    density : 1.,
                 },
             ]
         )
@@ -41,25 +89,103 @@ impl Default for BlockPalette {
 }
 // This is an insane amount of redirection.
 impl BlockPalette {
-    pub fn index_type(&self, index : usize) -> CollisionType {
-        self.0[index].collision_type
+    pub fn leaf_count(&self) -> usize { self.0.len() }
+
+    pub fn add(&mut self, block:Block) -> Leaf {
+        self.0.push(block);
+        Leaf(self.0.len() as u16 - 1)
+    }
+
+    pub fn block(&self, leaf:Leaf) -> Option<&Block> { self.0.get(leaf.0 as usize) }
+
+    /// Every block in leaf order.
+    pub fn write(&self, writer:&mut ByteWriter) {
+        writer.varint(self.0.len() as u64);
+        for block in &self.0 { block.write(writer) }
+    }
+
+    pub fn read(reader:&mut ByteReader) -> Option<Self> {
+        let mut blocks = Vec::new();
+        for _ in 0 .. reader.varint()? { blocks.push(Block::read(reader)?) }
+        Some(Self(blocks))
+    }
+
+    /// The block behind each leaf, for saves to carry their materials with them.
+    pub fn used(&self, leaves:&[Leaf]) -> Vec<(Leaf, Block)> {
+        leaves.iter().filter_map(|leaf| Some((*leaf, self.block(*leaf)?.clone()))).collect()
+    }
+
+    pub fn write_used(&self, writer:&mut ByteWriter, leaves:&[Leaf]) {
+        let used = self.used(leaves);
+        writer.varint(used.len() as u64);
+        for (leaf, block) in used {
+            writer.varint(leaf.0 as u64);
+            block.write(writer);
+        }
+    }
+
+    pub fn read_used(reader:&mut ByteReader) -> Option<Vec<(Leaf, Block)>> {
+        let mut used = Vec::new();
+        for _ in 0 .. reader.varint()? { used.push((Leaf(u16::try_from(reader.varint()?).ok()?), Block::read(reader)?)) }
+        Some(used)
+    }
+
+    /// Finds each saved block in the palette, without adding anything so a save can still be rejected.
+    /// Returns what each saved leaf will be called here once the missing blocks are appended, and those blocks in order.
+    pub fn plan_merge(&self, used:Vec<(Leaf, Block)>) -> (HashMap<Leaf, Leaf>, Vec<Block>) {
+        let mut missing:Vec<Block> = Vec::new();
+        let renamed = used.into_iter().map(|(saved, block)| {
+            let index = self.0.iter().chain(&missing).position(|known| *known == block).unwrap_or_else(|| {
+                missing.push(block);
+                self.0.len() + missing.len() - 1
+            });
+            (saved, Leaf(index as u16))
+        }).collect();
+        (renamed, missing)
+    }
+
+    /// Adds the blocks plan_merge found missing, along with a leaf for each.
+    pub fn append(&mut self, graph:&mut Graph, missing:Vec<Block>) {
+        for block in missing {
+            let leaf = graph.add_leaf();
+            assert_eq!(self.add(block), leaf, "Palette and graph leaves are out of sync");
+        }
+    }
+
+    pub fn leaf_type(&self, leaf : Leaf) -> CollisionType {
+        self.0.get(leaf.0 as usize).map_or(CollisionType::Void, |block| block.collision_type)
     }
     
     pub fn cell_type(&self, cell: Option<CellData>) -> CollisionType {
         match cell {
             None => CollisionType::Void,
-            Some(cell) => self.index_type(*cell.pointer.pointer)
+            Some(cell) => self.leaf_type(cell.leaf)
         }
     }
 
-    pub fn color(&self, index : usize) -> Color {
-        self.0[index].color
+    pub fn density(&self, leaf : Leaf) -> f32 {
+        self.0.get(leaf.0 as usize).map_or(0., |block| block.density)
+    }
+
+    pub fn color(&self, leaf : Leaf) -> Color {
+        self.0.get(leaf.0 as usize).map_or(BLANK, |block| block.color)
+    }
+
+    /// Nothing there at all, so there's nothing to draw or hit.
+    pub fn is_empty_leaf(&self, leaf : Leaf) -> bool {
+        self.block(leaf).is_none_or(|block| matches!(block.collision_type, CollisionType::Air) && block.color.a == 0.)
     }
 
     pub fn is_solid_cell(&self, cell: Option<CellData>) -> bool {
         matches!(self.cell_type(cell), CollisionType::Solid)
     }
-    pub fn is_solid_index(&self, index : usize) -> bool {
-        matches!(self.index_type(index), CollisionType::Solid)
+    pub fn is_solid_leaf(&self, leaf : Leaf) -> bool {
+        matches!(self.leaf_type(leaf), CollisionType::Solid)
+    }
+    pub fn is_sensor_cell(&self, cell: Option<CellData>) -> bool {
+        matches!(self.cell_type(cell), CollisionType::Sensor)
+    }
+    pub fn is_sensor_leaf(&self, leaf : Leaf) -> bool {
+        matches!(self.leaf_type(leaf), CollisionType::Sensor)
     }
 }
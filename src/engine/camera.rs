@@ -6,12 +6,56 @@ use macroquad::{
 use macroquad::math::Vec2;
 use derive_new::new;
 use crate::engine::math::Aabb;
+
+/// Where the camera's draw calls end up. Everything is already in screen space.
+#[allow(dead_code)]
+pub trait RenderSink : Send + Sync {
+    fn screen_size(&self) -> Vec2;
+    fn rectangle(&self, position:Vec2, length:Vec2, color:Color);
+    fn rectangle_lines(&self, position:Vec2, length:Vec2, thickness:f32, color:Color);
+    fn circle(&self, center:Vec2, radius:f32, color:Color);
+    fn circle_lines(&self, center:Vec2, radius:f32, thickness:f32, color:Color);
+    fn line(&self, point1:Vec2, point2:Vec2, thickness:f32, color:Color);
+    fn triangle(&self, points:[Vec2; 3], color:Color);
+    fn triangle_lines(&self, points:[Vec2; 3], thickness:f32, color:Color);
+}
+
+/// Draws to the macroquad window.
+pub struct ScreenSink;
+impl RenderSink for ScreenSink {
+    fn screen_size(&self) -> Vec2 { Vec2::from(screen_size()) }
+    fn rectangle(&self, position:Vec2, length:Vec2, color:Color) { draw_rectangle(position.x, position.y, length.x, length.y, color) }
+    fn rectangle_lines(&self, position:Vec2, length:Vec2, thickness:f32, color:Color) {
+        draw_rectangle_lines(position.x, position.y, length.x, length.y, thickness, color)
+    }
+    fn circle(&self, center:Vec2, radius:f32, color:Color) { draw_circle(center.x, center.y, radius, color) }
+    fn circle_lines(&self, center:Vec2, radius:f32, thickness:f32, color:Color) { draw_circle_lines(center.x, center.y, radius, thickness, color) }
+    fn line(&self, point1:Vec2, point2:Vec2, thickness:f32, color:Color) { draw_line(point1.x, point1.y, point2.x, point2.y, thickness, color) }
+    fn triangle(&self, points:[Vec2; 3], color:Color) { draw_triangle(points[0], points[1], points[2], color) }
+    fn triangle_lines(&self, points:[Vec2; 3], thickness:f32, color:Color) { draw_triangle_lines(points[0], points[1], points[2], thickness, color) }
+}
+
+/// Swallows every draw call, for running without a window.
+pub struct NoOpSink;
+impl RenderSink for NoOpSink {
+    fn screen_size(&self) -> Vec2 { Vec2::ONE }
+    fn rectangle(&self, _:Vec2, _:Vec2, _:Color) {}
+    fn rectangle_lines(&self, _:Vec2, _:Vec2, _:f32, _:Color) {}
+    fn circle(&self, _:Vec2, _:f32, _:Color) {}
+    fn circle_lines(&self, _:Vec2, _:f32, _:f32, _:Color) {}
+    fn line(&self, _:Vec2, _:Vec2, _:f32, _:Color) {}
+    fn triangle(&self, _:[Vec2; 3], _:Color) {}
+    fn triangle_lines(&self, _:[Vec2; 3], _:f32, _:Color) {}
+}
+
 #[derive(new)]
 pub struct Camera { 
     position: Vec2,
     radius: f32,
     #[new(value = "1.")]
     scale: f32,
+    #[new(value = "Box::new(ScreenSink)")]
+    sink: Box<dyn RenderSink>,
 }
 // State changes
 impl Camera {
@@ -23,7 +67,11 @@ impl Camera {
     }
 
     pub fn update_scale(&mut self) {
-        self.scale = (Vec2::from(screen_size())).min_element() / (2. * self.radius);
+        self.scale = self.sink.screen_size().min_element() / (2. * self.radius);
+    }
+
+    pub fn set_sink(&mut self, sink:Box<dyn RenderSink>) {
+        self.sink = sink;
     }
 
     pub fn move_to(&mut self, new_position:Vec2, smoothing:f32) {
@@ -34,6 +82,16 @@ impl Camera {
         self.radius /= zoom;
     }
 
+    pub fn position(&self) -> Vec2 { self.position }
+
+    pub fn radius(&self) -> f32 { self.radius }
+
+    /// Jumps straight to a saved view, scale catches up on the next update.
+    pub fn set_view(&mut self, position:Vec2, radius:f32) {
+        self.position = position;
+        self.radius = radius;
+    }
+
     fn lerp_position(&mut self, position:Vec2, smoothing:f32) {
         self.position = self.position.lerp(position, smoothing);
     }
@@ -42,7 +100,7 @@ impl Camera {
 // Conversions between screen and world spaces
 impl Camera {
     fn global_offset(&self) -> Vec2 {
-        self.position - Vec2::from(screen_size()) / 2. / self.scale
+        self.position - self.sink.screen_size() / 2. / self.scale
     }
 
     pub fn world_to_screen(&self, world_position:Vec2) -> Vec2 {
@@ -85,31 +143,29 @@ impl Camera {
 
     pub fn draw_vec_rectangle(&self, position:Vec2, length:Vec2, color:Color) {
         let pos = self.world_to_screen(position);
-        let len = length * self.scale;
-        draw_rectangle(pos.x, pos.y, len.x, len.y, color);
+        self.sink.rectangle(pos, length * self.scale, color);
     }
 
     pub fn outline_vec_rectangle(&self, position:Vec2, length:Vec2, line_width:f32, color:Color) {
         let pos = self.world_to_screen(position);
-        let len = length * self.scale;
-        draw_rectangle_lines(pos.x, pos.y, len.x, len.y, line_width*self.scale, color);
+        self.sink.rectangle_lines(pos, length * self.scale, line_width*self.scale, color);
     }
     
     pub fn draw_point(&self, position:Vec2, radius:f32, color:Color) {
         let pos = self.world_to_screen(position);
-        draw_circle(pos.x, pos.y, radius*self.scale, color);
+        self.sink.circle(pos, radius*self.scale, color);
     }
 
     pub fn outline_point(&self, position:Vec2, radius:f32, thickness:f32, color:Color) {
         let pos = self.world_to_screen(position);
-        draw_circle_lines(pos.x, pos.y, radius*self.scale, thickness*self.scale, color);
+        self.sink.circle_lines(pos, radius*self.scale, thickness*self.scale, color);
     }
 
 
     pub fn draw_vec_line(&self, point1:Vec2, point2:Vec2, color:Color) {
         let p1 = self.world_to_screen(point1);
         let p2 = self.world_to_screen(point2);
-        draw_line(p1.x, p1.y, p2.x, p2.y, 2., color);
+        self.sink.line(p1, p2, 2., color);
     }
 
     pub fn outline_bounds(&self, bounds:Aabb, line_width:f32, color:Color) {
@@ -118,33 +174,11 @@ impl Camera {
 
     pub fn draw_rectangle_from_corners(&self, corners:&[Vec2], color: Color, render_dbg:bool) {
         let corners:Vec<Vec2> = corners.iter().map(|point| self.world_to_screen(*point)).collect();
-        draw_triangle(
-            corners[0],
-            corners[1],
-            corners[2],
-            color
-        );
-        draw_triangle(
-            corners[1],
-            corners[2],
-            corners[3],
-            color
-        );
+        self.sink.triangle([corners[0], corners[1], corners[2]], color);
+        self.sink.triangle([corners[1], corners[2], corners[3]], color);
         if render_dbg {
-            draw_triangle_lines(
-                corners[0],
-                corners[1],
-                corners[2],
-                2.,
-                WHITE
-            );
-            draw_triangle_lines(
-                corners[1],
-                corners[2],
-                corners[3],
-                2.,
-                WHITE
-            );
+            self.sink.triangle_lines([corners[0], corners[1], corners[2]], 2., WHITE);
+            self.sink.triangle_lines([corners[1], corners[2], corners[3]], 2., WHITE);
         }
     }
 
@@ -153,7 +187,7 @@ impl Camera {
         for point in 0 .. points.len() {
             let point1 = points[point];
             let point2 = points[(point + 1) % points.len()];
-            draw_line(point1.x, point1.y, point2.x, point2.y, 4., color);
+            self.sink.line(point1, point2, 4., color);
         }
     }
 
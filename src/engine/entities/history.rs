@@ -0,0 +1,175 @@
+use std::collections::{HashMap, VecDeque};
+use vec_mem_heap::prelude::AccessError;
+use super::{Entity, ExternalPointer};
+use crate::engine::grid::dag::Index;
+use crate::engine::blocks::BlockPalette;
+use crate::engine::world::Graph;
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 64;
+
+/// Roots an entity has edited away from (undo) or undone (redo).
+/// Every root in here holds a reference to its whole tree, so the graph can't recycle it.
+pub struct EditHistory {
+    undo: VecDeque<ExternalPointer>,
+    redo: Vec<ExternalPointer>,
+    depth: usize,
+    grouping: bool,
+    group_recorded: bool,
+}
+impl EditHistory {
+    pub fn new(depth:usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+            grouping: false,
+            group_recorded: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_depth(&mut self, graph:&mut Graph, depth:usize) {
+        self.depth = depth;
+        while self.undo.len() > self.depth {
+            graph.remove_tree_ref(self.undo.pop_front().unwrap().pointer);
+        }
+    }
+
+    /// Every edit until end_group is undone in one step, useful for brush strokes.
+    pub fn begin_group(&mut self) {
+        self.grouping = true;
+        self.group_recorded = false;
+    }
+
+    pub fn end_group(&mut self) { self.grouping = false }
+
+    /// Releases every root held by the history.
+    pub fn clear(&mut self, graph:&mut Graph) {
+        for root in self.undo.drain(..).chain(self.redo.drain(..)) {
+            graph.remove_tree_ref(root.pointer);
+        }
+    }
+
+    /// The undo roots oldest first, then the redo roots.
+    pub fn stacks(&self) -> (Vec<ExternalPointer>, Vec<ExternalPointer>) {
+        (self.undo.iter().copied().collect(), self.redo.clone())
+    }
+
+    /// Takes over roots which already hold a reference each, in the order stacks gives them.
+    pub fn set_stacks(&mut self, graph:&mut Graph, undo:Vec<ExternalPointer>, redo:Vec<ExternalPointer>) {
+        self.clear(graph);
+        self.undo = undo.into();
+        self.redo = redo;
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = Index> + '_ {
+        self.undo.iter().chain(self.redo.iter()).map(|root| root.pointer)
+    }
+
+    /// Patches every held root after the graph has been compacted.
+    pub fn remap(&mut self, remapped:&HashMap<Index, Index>) {
+        for root in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            root.pointer = remapped[&root.pointer];
+        }
+    }
+
+    // Takes ownership of a root which already holds a tree reference.
+    fn push(&mut self, graph:&mut Graph, root:ExternalPointer) {
+        for undone in self.redo.drain(..) {
+            graph.remove_tree_ref(undone.pointer);
+        }
+        self.undo.push_back(root);
+        while self.undo.len() > self.depth {
+            graph.remove_tree_ref(self.undo.pop_front().unwrap().pointer);
+        }
+    }
+}
+
+impl Entity {
+    /// Runs edit against the current root and records the old root so it can be undone.
+    pub fn edit_root<F>(&mut self, graph:&mut Graph, blocks:&BlockPalette, edit:F) -> Result<(), AccessError>
+    where F: FnOnce(&mut Graph, ExternalPointer) -> Result<ExternalPointer, AccessError> {
+        let old_root = self.location.pointer;
+        let record = !(self.history.grouping && self.history.group_recorded);
+        // Held before the edit, otherwise the graph is free to rewrite old nodes in place
+        if record { graph.add_tree_ref(old_root.pointer) }
+        let new_root = match edit(graph, old_root) {
+            Ok(new_root) => new_root,
+            Err(error) => {
+                if record { graph.remove_tree_ref(old_root.pointer) }
+                return Err(error)
+            }
+        };
+        if record && new_root.pointer != old_root.pointer {
+            self.history.push(graph, old_root);
+            self.history.group_recorded = true;
+        } else if record { graph.remove_tree_ref(old_root.pointer) }
+        // Unrecorded edits can rewrite nodes in place and hand back the same root, which still changes the shape
+        self.set_root(graph, blocks, new_root);
+        Ok(())
+    }
+
+    /// Returns whether there was anything to undo
+    pub fn undo(&mut self, graph:&mut Graph, blocks:&BlockPalette) -> bool {
+        let Some(previous) = self.history.undo.pop_back() else { return false };
+        // The references move along with the roots, so there's nothing to count
+        self.history.redo.push(self.location.pointer);
+        self.set_root(graph, blocks, previous);
+        true
+    }
+
+    /// Returns whether there was anything to redo
+    pub fn redo(&mut self, graph:&mut Graph, blocks:&BlockPalette) -> bool {
+        let Some(next) = self.history.redo.pop() else { return false };
+        self.history.undo.push_back(self.location.pointer);
+        self.set_root(graph, blocks, next);
+        true
+    }
+
+    /// Drops the current root's reference along with every root in the history, for an entity that's gone for good.
+    pub fn release(&mut self, graph:&mut Graph) {
+        self.history.clear(graph);
+        graph.remove_tree_ref(self.location.pointer.pointer);
+    }
+}
+
+#[test]
+fn released_entities_free_their_nodes() {
+    use macroquad::math::Vec2;
+    use crate::engine::world::World;
+    use crate::engine::grid::dag::Leaf;
+    let mut world = World::default();
+    let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::ZERO, 2);
+    let solid = world.graph.leaf_pointer(Leaf(1)).unwrap();
+    for path in [[0, 0], [3, 3]] {
+        entity.edit_root(&mut world.graph, &world.blocks, |graph, root| graph.set_node(root, &path, solid)).unwrap();
+    }
+    entity.undo(&mut world.graph, &world.blocks);
+    assert!(world.graph.index_lookup.len() > world.graph.leaf_count());
+    // Whatever's current, undone or redoable, nothing but the leaves is left
+    entity.release(&mut world.graph);
+    assert_eq!(world.graph.index_lookup.len(), world.graph.leaf_count());
+}
+
+#[test]
+fn grouped_edits_keep_the_shape_current() {
+    use macroquad::math::Vec2;
+    use crate::engine::world::World;
+    use crate::engine::grid::dag::Leaf;
+    let mut world = World::default();
+    let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::ZERO, 2);
+    let solid = world.graph.leaf_pointer(Leaf(1)).unwrap();
+    entity.history.begin_group();
+    for path in [[0, 0], [0, 1], [3, 3]] {
+        entity.edit_root(&mut world.graph, &world.blocks, |graph, root| graph.set_node(root, &path, solid)).unwrap();
+    }
+    entity.history.end_group();
+    let (corners, mass) = (entity.corners.len(), entity.mass);
+    let root = entity.location.pointer;
+    entity.set_root(&mut world.graph, &world.blocks, root);
+    assert_eq!((corners, mass), (entity.corners.len(), entity.mass));
+    assert_eq!(mass.mass, 3.);
+    // The whole stroke is one undo
+    assert!(entity.undo(&mut world.graph, &world.blocks));
+    assert_eq!(entity.mass.mass, 0.);
+}
@@ -1,28 +1,117 @@
 mod render;
 mod movement;
 mod serialization;
+mod history;
+pub use history::{EditHistory, DEFAULT_HISTORY_DEPTH};
 use serde::{Serialize, Deserialize};
 use macroquad::math::Vec2;
-use crate::engine::grid::dag::ExternalPointer;
+use crate::engine::grid::dag::{ExternalPointer, Leaf};
 use crate::engine::math::Aabb;
 use crate::engine::grid::partition::*;
 use crate::engine::physics::collisions::{Corners, corner_handling};
+use crate::engine::physics::rigid_body::{Body, MassProperties};
+use crate::engine::blocks::BlockPalette;
+use crate::engine::world::Graph;
 
 
+// Entities live in slots, a handle is a slot index plus the slot's generation when the entity was spawned.
+// Despawning bumps the generation, so handles to despawned entities stop resolving instead of finding whatever reused the slot.
+struct Slot {
+    generation: u32,
+    entity: Option<Entity>,
+}
+
 #[derive(derive_new::new)]
 pub struct EntityPool {
     #[new(value = "Vec::new()")]
-    pub entities: Vec<Entity>,
+    slots: Vec<Slot>,
+    // Empty slots, the most recently freed is reused first
+    #[new(value = "Vec::new()")]
+    free: Vec<u32>,
 }
 impl EntityPool {
-    pub fn add_to_pool(&mut self, entity:Entity) {
-        self.entities.push(entity);
+    /// Adds the entity under a new handle, which is also written to its id.
+    pub fn spawn(&mut self, mut entity:Entity) -> ID {
+        let index = self.free.pop().unwrap_or_else(|| {
+            self.slots.push(Slot { generation: 1, entity: None });
+            self.slots.len() as u32 - 1
+        });
+        let slot = &mut self.slots[index as usize];
+        let id = ID { index, generation: slot.generation };
+        entity.id = id;
+        slot.entity = Some(entity);
+        id
+    }
+    /// Adds the entity back under a handle it had before, so anything still holding that handle finds it again.
+    /// The slot has to be empty.
+    pub fn respawn(&mut self, mut entity:Entity, id:ID) {
+        while self.slots.len() <= id.index as usize {
+            // Gaps are filled after every slot freed before them
+            self.free.insert(0, self.slots.len() as u32);
+            self.slots.push(Slot { generation: 1, entity: None });
+        }
+        self.free.retain(|index| *index != id.index);
+        let slot = &mut self.slots[id.index as usize];
+        assert!(slot.entity.is_none(), "Respawned into a taken slot");
+        slot.generation = id.generation;
+        entity.id = id;
+        slot.entity = Some(entity);
+    }
+    pub fn despawn(&mut self, id:ID) -> Option<Entity> {
+        let slot = self.slots.get_mut(id.index as usize).filter(|slot| slot.generation == id.generation)?;
+        let entity = slot.entity.take()?;
+        slot.generation += 1;
+        self.free.push(id.index);
+        Some(entity)
     }
     pub fn get_mut_entity(&mut self, id:ID) -> Option<&mut Entity> {
-        self.entities.iter_mut().find(|entity| entity.id == id)
+        self.slots.get_mut(id.index as usize).filter(|slot| slot.generation == id.generation)?.entity.as_mut()
     }
     pub fn get_entity(&self, id:ID) -> Option<&Entity> {
-        self.entities.iter().find(|entity| entity.id == id)
+        self.slots.get(id.index as usize).filter(|slot| slot.generation == id.generation)?.entity.as_ref()
+    }
+    /// Live entities in slot order, which is the order everything that loops over entities uses.
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.slots.iter().filter_map(|slot| slot.entity.as_ref())
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Entity> {
+        self.slots.iter_mut().filter_map(|slot| slot.entity.as_mut())
+    }
+    pub fn ids(&self) -> Vec<ID> {
+        self.iter().map(|entity| entity.id).collect()
+    }
+    pub fn len(&self) -> usize { self.iter().count() }
+    pub fn is_empty(&self) -> bool { self.iter().next().is_none() }
+    /// Despawns everything, later spawns fill the slots from the first again.
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut().filter(|slot| slot.entity.is_some()) {
+            slot.entity = None;
+            slot.generation += 1;
+        }
+        self.free = (0 .. self.slots.len() as u32).rev().collect();
+    }
+    /// The live entity after this one in slot order, wrapping around. Works for despawned handles too.
+    pub fn next_id(&self, id:ID) -> Option<ID> {
+        let ids = self.ids();
+        ids.iter().find(|next| next.index > id.index).or(ids.first()).copied()
+    }
+    /// Remembers every entity's pose before a tick moves them.
+    pub fn store_poses(&mut self) {
+        for entity in self.iter_mut() {
+            entity.last_position = entity.location.position;
+            entity.last_rotation = entity.rotation;
+        }
+    }
+    /// Compacts the graph around every entity's root, then points each entity at its new root.
+    pub fn compact_graph(&mut self, graph:&mut Graph) {
+        let roots:Vec<_> = self.iter()
+            .flat_map(|entity| std::iter::once(entity.location.pointer.pointer).chain(entity.history.roots()))
+            .collect();
+        let remapped = graph.compact(&roots);
+        for entity in self.iter_mut() {
+            entity.location.pointer.pointer = remapped[&entity.location.pointer.pointer];
+            entity.history.remap(&remapped);
+        }
     }
 }
 
@@ -39,7 +128,17 @@ impl Location {
     }
 }
 
-pub type ID = u32;
+/// A handle to an entity in an EntityPool. Generations start at 1, so the default handle never resolves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ID {
+    index: u32,
+    generation: u32,
+}
+impl ID {
+    /// Slots are reused, so this is only unique among live entities.
+    pub fn index(&self) -> u32 { self.index }
+}
+
 // Chunk and store corner locations in u8s?
 pub struct Entity {
     pub id : ID,
@@ -49,9 +148,43 @@ pub struct Entity {
     pub velocity: Vec2,
     pub angular_velocity: f32,
     pub corners : Vec<Corners>,
+    pub history : EditHistory,
+    // Kinematic entities move under their own velocity but collisions never push them
+    pub kinematic: bool,
+    pub body: Body,
+    // Kept in step with the grid by set_root
+    pub mass: MassProperties,
+    // Pose at the start of the current tick, rendering blends from here to the current pose
+    pub last_position: Vec2,
+    pub last_rotation: f32,
 }
 impl Entity {
-    pub fn recaclulate_corners(&mut self) { self.corners = corner_handling::tree_corners(self.location.pointer, self.location.min_cell_length) }
+    /// An entity whose grid is entirely empty.
+    pub fn blank(graph:&mut Graph, blocks:&BlockPalette, position:Vec2, height:u32) -> Entity {
+        let location = Location::new(position, graph.get_root(Leaf(0), height));
+        Entity {
+            id: ID::default(),
+            location,
+            rotation: 0.,
+            forward: Vec2::from_angle(0.),
+            velocity: Vec2::ZERO,
+            angular_velocity: 0.,
+            corners: corner_handling::tree_corners(graph, blocks, location.pointer, location.min_cell_length),
+            history: EditHistory::new(DEFAULT_HISTORY_DEPTH),
+            kinematic: false,
+            body: Body::default(),
+            mass: MassProperties::from_tree(graph, blocks, location.pointer, location.min_cell_length),
+            last_position: position,
+            last_rotation: 0.,
+        }
+    }
+
+    pub fn recaclulate_corners(&mut self, graph:&Graph, blocks:&BlockPalette) {
+        self.corners = corner_handling::tree_corners(graph, blocks, self.location.pointer, self.location.min_cell_length)
+    }
+    pub fn recalculate_mass(&mut self, graph:&mut Graph, blocks:&BlockPalette) {
+        self.mass = MassProperties::from_tree(graph, blocks, self.location.pointer, self.location.min_cell_length)
+    }
     pub fn aabb(&self) -> Option<Aabb> {
         let (mut top_left, mut bottom_right) = self.get_extreme_points()?;
         top_left += -center_to_edge(self.location.pointer.height, self.location.min_cell_length) + self.location.position;
@@ -67,7 +200,7 @@ impl Entity {
         let mut top_left = Vec2::NAN;
         let mut bottom_right = Vec2::NAN;
         
-        self.corners.iter().filter(|corner| *corner.index != 0)
+        self.corners.iter().filter(|corner| corner.leaf != Leaf(0))
             .flat_map(|corner| &corner.points)
             .for_each(|pos| {
                 // Update top-left (minimum x and y)
@@ -130,3 +263,22 @@ impl EntityBuilder {
 
 }
     */
+
+#[test]
+fn stale_handles_miss_reused_slots() {
+    use crate::engine::world::World;
+    let mut world = World::default();
+    let spawn = |world:&mut World, x:f32| {
+        let entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::new(x, 0.), 1);
+        world.entities.spawn(entity)
+    };
+    let first = spawn(&mut world, 0.);
+    let second = spawn(&mut world, 1.);
+    assert_eq!(world.entities.next_id(second), Some(first));
+    assert!(world.entities.despawn(first).is_some());
+    let third = spawn(&mut world, 2.);
+    assert_eq!(third.index(), first.index());
+    assert!(world.entities.get_entity(first).is_none() && world.entities.despawn(first).is_none());
+    assert_eq!(world.entities.get_entity(third).unwrap().location.position.x, 2.);
+    assert_eq!(world.entities.len(), 2);
+}
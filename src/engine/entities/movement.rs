@@ -1,18 +1,23 @@
 use std::f32::consts::PI;
 use macroquad::math::Vec2;
 use super::{Entity, ExternalPointer};
+use crate::engine::blocks::BlockPalette;
+use crate::engine::world::Graph;
 
 #[allow(dead_code)]
 impl Entity {
+    /// Where the entity turns about, its center of mass in world space.
+    pub fn pivot(&self) -> Vec2 { self.location.position + self.mass.center_of_mass.rotate(self.forward) }
+    // Corners are stored in the grid's local space, so rotating doesn't touch them.
+    // The grid turns about the pivot, which drags its center along with it
     pub fn rel_rotate(&mut self, angle: f32) {
+        let pivot = self.pivot();
         self.rotation = (self.rotation + angle).rem_euclid(PI * 2.);
         self.forward = Vec2::from_angle(self.rotation);
-        self.recaclulate_corners();
+        self.location.position = pivot - self.mass.center_of_mass.rotate(self.forward);
     }
     pub fn set_rotation(&mut self, angle: f32) { 
-        self.rotation = angle.rem_euclid(PI * 2.);
-        self.forward = Vec2::from_angle(self.rotation);
-        self.recaclulate_corners();
+        self.rel_rotate(angle - self.rotation);
     }
     pub fn apply_forward_velocity(&mut self, speed:f32) { self.velocity += self.forward * speed }
     pub fn apply_perp_velocity(&mut self, speed:f32) { self.velocity += self.forward.perp() * speed }
@@ -21,8 +26,34 @@ impl Entity {
         self.velocity = Vec2::ZERO; 
         self.angular_velocity = 0.0;
     }
-    pub fn set_root(&mut self, new_root:ExternalPointer) { 
+    /// Position and forward vector blended between the last tick and this one.
+    pub fn interpolated_pose(&self, alpha:f32) -> (Vec2, Vec2) {
+        // Rotation wraps at 2PI so blend along whichever way round is shorter
+        let turn = (self.rotation - self.last_rotation + PI).rem_euclid(PI * 2.) - PI;
+        let forward = Vec2::from_angle(self.last_rotation + turn * alpha);
+        // The pivot moves in a straight line while the grid's center swings around it
+        let center_of_mass = self.mass.center_of_mass;
+        let last_pivot = self.last_position + center_of_mass.rotate(Vec2::from_angle(self.last_rotation));
+        (last_pivot.lerp(self.pivot(), alpha) - center_of_mass.rotate(forward), forward)
+    }
+    pub fn set_root(&mut self, graph:&mut Graph, blocks:&BlockPalette, new_root:ExternalPointer) { 
         self.location.pointer = new_root;
-        self.recaclulate_corners();
+        self.recaclulate_corners(graph, blocks);
+        self.recalculate_mass(graph, blocks);
     }
-}
\ No newline at end of file
+}
+#[test]
+fn lopsided_entities_turn_about_their_mass() {
+    use macroquad::math::BVec2;
+    use crate::engine::world::World;
+    let mut world = World::default();
+    let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::ZERO, 1);
+    let solid = world.graph.leaf_pointer(crate::engine::grid::dag::Leaf(1)).unwrap();
+    let root = world.graph.set_node(entity.location.pointer, &[0], solid).unwrap();
+    entity.set_root(&mut world.graph, &world.blocks, root);
+    let pivot = entity.pivot();
+    assert_ne!(pivot, entity.location.position);
+    entity.rel_rotate(PI / 2.);
+    assert_eq!((entity.pivot() - pivot).abs().cmplt(Vec2::splat(1e-5)), BVec2::TRUE);
+    assert_ne!(entity.location.position, Vec2::ZERO);
+}
@@ -1,57 +1,61 @@
 use super::*;
-use crate::globals::*;
-use crate::engine::grid::dag::Index;
+use crate::engine::grid::dag::Leaf;
+use crate::engine::camera::Camera;
 impl EntityPool {
-    pub fn draw_all(&self, rotate:bool, render_dbg:bool) {
-        for entity in self.entities.iter() {
-            entity.draw(rotate, render_dbg);
-            entity.draw_velocity_arrow(macroquad::color::DARKBLUE);
+    /// Alpha is how far between the last two ticks to draw each entity.
+    pub fn draw_all(&self, camera:&Camera, blocks:&BlockPalette, rotate:bool, render_dbg:bool, alpha:f32) {
+        for entity in self.iter() {
+            entity.draw(camera, blocks, rotate, render_dbg, alpha);
+            entity.draw_velocity_arrow(camera, macroquad::color::DARKBLUE, alpha);
         }
     }
 
 }
 
 impl Entity {
-    pub fn draw_velocity_arrow(&self, color: macroquad::color::Color) {
-        CAMERA.read().draw_vec_line(
-            self.location.position, 
-            self.location.position + self.velocity * 5.,
+    pub fn draw_velocity_arrow(&self, camera:&Camera, color: macroquad::color::Color, alpha:f32) {
+        let (position, _) = self.interpolated_pose(alpha);
+        camera.draw_vec_line(
+            position, 
+            position + self.velocity * 5.,
             color
         );
     }
 
-    pub fn draw(&self, rotate:bool, render_dbg:bool) {
+    pub fn draw(&self, camera:&Camera, blocks:&BlockPalette, rotate:bool, render_dbg:bool, alpha:f32) {
         let point_offset = center_to_edge(self.location.pointer.height, self.location.min_cell_length);
-        let rotation = if rotate { self.forward } else { Vec2::new(1., 0.) };
-        let points_list: Vec<([Vec2; 4], usize)> = self.corners.iter().map(|cell| {
+        let (position, forward) = self.interpolated_pose(alpha);
+        let rotation = if rotate { forward } else { Vec2::new(1., 0.) };
+        let points_list: Vec<([Vec2; 4], Leaf)> = self.corners.iter().map(|cell| {
             ([
-                    (cell.points[0] - point_offset).rotate(rotation) + self.location.position,
-                    (cell.points[1] - point_offset).rotate(rotation) + self.location.position,
-                    (cell.points[2] - point_offset).rotate(rotation) + self.location.position,
-                    (cell.points[3] - point_offset).rotate(rotation) + self.location.position
-                ], *cell.index
+                    (cell.points[0] - point_offset).rotate(rotation) + position,
+                    (cell.points[1] - point_offset).rotate(rotation) + position,
+                    (cell.points[2] - point_offset).rotate(rotation) + position,
+                    (cell.points[3] - point_offset).rotate(rotation) + position
+                ], cell.leaf
             )
         }).collect();
-        for (points, index) in points_list {
-            CAMERA.read().draw_rectangle_from_corners(
+        for (points, leaf) in points_list {
+            camera.draw_rectangle_from_corners(
                 &points,
-                BLOCKS.color(index),
+                blocks.color(leaf),
                 render_dbg,
             );
         }
     }
     
-    pub fn draw_outline(&self, color:macroquad::color::Color) {
+    pub fn draw_outline(&self, graph:&Graph, blocks:&BlockPalette, camera:&Camera, color:macroquad::color::Color, alpha:f32) {
+        let (position, forward) = self.interpolated_pose(alpha);
         let point_offset = center_to_edge(self.location.pointer.height, self.location.min_cell_length);
-        let square = ExternalPointer::new(Index(1), self.location.pointer.height);
-        let corners = corner_handling::tree_corners(square, self.location.min_cell_length)[0].points;
+        let square = ExternalPointer::new(graph.leaf_pointer(Leaf(1)).unwrap(), self.location.pointer.height);
+        let corners = corner_handling::tree_corners(graph, blocks, square, self.location.min_cell_length)[0].points;
         let points = [
-            (corners[0] - point_offset).rotate(self.forward) + self.location.position,
-            (corners[1] - point_offset).rotate(self.forward) + self.location.position,
-            (corners[3] - point_offset).rotate(self.forward) + self.location.position,
-            (corners[2] - point_offset).rotate(self.forward) + self.location.position,
+            (corners[0] - point_offset).rotate(forward) + position,
+            (corners[1] - point_offset).rotate(forward) + position,
+            (corners[3] - point_offset).rotate(forward) + position,
+            (corners[2] - point_offset).rotate(forward) + position,
         ];
-        CAMERA.read().draw_outline(&points, color);
+        camera.draw_outline(&points, color);
     }
 
 }
@@ -1,37 +1,192 @@
 
-use super::{Entity, EntityPool, Vec2, Location, ID, corner_handling};
+use super::{Entity, EntityPool, Vec2, Location, ID, ExternalPointer, corner_handling, EditHistory, DEFAULT_HISTORY_DEPTH, Body, MassProperties};
 use serde::{Serialize, Deserialize};
-use crate::globals::GRAPH;
+use crate::engine::binary::{ByteReader, ByteWriter, has_magic};
+use crate::engine::blocks::{Block, BlockPalette};
+use crate::engine::grid::dag::Leaf;
+use crate::engine::grid::dag::LoadError;
+use crate::engine::world::{World, Graph};
+
+const ENTITY_MAGIC: &[u8; 4] = b"GGEN";
+const SCENE_MAGIC: &[u8; 4] = b"GGSC";
+
+// Position, rotation, velocity, angular velocity, whether it's kinematic and how it collides
+type Fields = (Vec2, f32, Vec2, f32, bool, Body);
 
 impl EntityPool {
-    pub fn save_entity(&self, id:ID) -> String {
-        self.get_entity(id).unwrap().save()
+    pub fn save_entity(&self, graph:&Graph, blocks:&BlockPalette, id:ID) -> String {
+        self.get_entity(id).unwrap().save(graph, blocks)
+    }
+
+    pub fn save_entity_binary(&self, graph:&Graph, blocks:&BlockPalette, id:ID) -> Vec<u8> {
+        self.get_entity(id).unwrap().save_binary(graph, blocks)
     }
     
 }
 
+impl World {
+    /// Saves every entity and the camera, with all their trees sharing one node table and the blocks they're made of.
+    /// Handles are saved so anything keyed by them still finds its entity after loading, the target is stored as its place among the saved entities.
+    pub fn save_scene(&self, target:ID) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.header(SCENE_MAGIC);
+        writer.vec2(self.camera.position());
+        writer.f32(self.camera.radius());
+        let entities:Vec<_> = self.entities.iter().collect();
+        writer.varint(entities.iter().position(|entity| entity.id == target).unwrap_or(0) as u64);
+        writer.varint(entities.len() as u64);
+        for entity in &entities {
+            writer.varint(entity.id.index as u64);
+            writer.varint(entity.id.generation as u64);
+            entity.write_fields(&mut writer);
+        }
+        let roots:Vec<_> = entities.iter().map(|entity| entity.location.pointer).collect();
+        self.blocks.write_used(&mut writer, &self.graph.leaves_in(&roots));
+        self.graph.write_forest(&mut writer, &roots);
+        writer.finish()
+    }
+
+    /// Replaces every entity with the scene's under the handles they were saved with and moves the camera to its view, returning the target's handle.
+    /// Scenes from before handles were saved get new ones. On failure the current scene and palette are left untouched.
+    pub fn load_scene(&mut self, data:&[u8]) -> Result<ID, LoadError> {
+        let mut reader = ByteReader::new(data);
+        let mut read_header = || {
+            let version = reader.header(SCENE_MAGIC)?;
+            let view = (reader.vec2()?, reader.f32()?);
+            let target = reader.varint()?;
+            let mut ids = Vec::new();
+            let mut handles:Vec<ID> = Vec::new();
+            let mut fields = Vec::new();
+            for _ in 0 .. reader.varint()? {
+                if version == 1 { ids.push(reader.varint()?) }
+                if version >= 5 {
+                    let handle = ID { index: u32::try_from(reader.varint()?).ok()?, generation: u32::try_from(reader.varint()?).ok()? };
+                    // Generations start at 1, and no two entities share a slot
+                    if handle.generation == 0 || handles.iter().any(|other| other.index == handle.index) { return None }
+                    handles.push(handle);
+                }
+                fields.push(Entity::read_fields(&mut reader, version)?);
+            }
+            // Version 1 saved ids rather than places, and every entity but the target was static
+            let target = if version == 1 {
+                for (id, fields) in ids.iter().zip(&mut fields) { fields.4 = *id != target }
+                ids.iter().position(|id| *id == target)?
+            } else { usize::try_from(target).ok()? };
+            let used = if version >= 4 { BlockPalette::read_used(&mut reader)? } else { Vec::new() };
+            (target < fields.len()).then_some((view, target, handles, fields, used))
+        };
+        let ((position, radius), target, handles, fields, used) = read_header().ok_or(LoadError::Malformed)?;
+        let (renamed, missing) = self.blocks.plan_merge(used);
+        let forest = self.graph.check_forest(&mut reader, &renamed, missing.len())?;
+        if forest.len() != fields.len() { return Err(LoadError::Malformed) }
+        self.blocks.append(&mut self.graph, missing);
+        let roots = self.graph.add_trees(forest);
+        for entity in self.entities.iter_mut() { entity.history.clear(&mut self.graph) }
+        self.entities.clear();
+        self.contacts.clear();
+        self.collisions.reset();
+        let ids:Vec<_> = fields.into_iter().zip(roots).enumerate().map(|(i, (fields, pointer))| {
+            let entity = Entity::from_parts(&mut self.graph, &self.blocks, fields, pointer);
+            match handles.get(i) {
+                Some(&handle) => { self.entities.respawn(entity, handle); handle }
+                None => self.entities.spawn(entity),
+            }
+        }).collect();
+        // Nothing references the old scene's trees anymore
+        self.entities.compact_graph(&mut self.graph);
+        self.camera.set_view(position, radius);
+        Ok(ids[target])
+    }
+}
+
 impl Entity {
-    pub fn save(&self) -> String {
+    pub fn save(&self, graph:&Graph, blocks:&BlockPalette) -> String {
         serde_json::to_string_pretty(&EntityStorer {
             position: self.location.position,
             rotation: self.rotation,
             velocity: self.velocity,
             angular_velocity: self.angular_velocity,
-            graph: GRAPH.read().save_object_json(self.location.pointer),
+            kinematic: self.kinematic,
+            body: self.body,
+            blocks: blocks.used(&graph.leaves_in(&[self.location.pointer])),
+            graph: graph.save_object_json(self.location.pointer),
         }).unwrap()
     }
-    pub fn load(data:String, id:ID) -> Entity {
-        let storer: EntityStorer = serde_json::from_str(&data).unwrap();
-        let pointer = GRAPH.write().load_object_json(storer.graph);
-        let location = Location::new(storer.position, pointer);
+    /// Entities come back without a handle until they're spawned into a pool.
+    /// Blocks the palette doesn't have yet are added to it once the tree's been checked, and the tree is remapped onto the palette's leaves.
+    pub fn load(graph:&mut Graph, blocks:&mut BlockPalette, data:String) -> Result<Entity, LoadError> {
+        let storer: EntityStorer = serde_json::from_str(&data)?;
+        let (renamed, missing) = blocks.plan_merge(storer.blocks);
+        let tree = graph.check_object_json(storer.graph, &renamed, missing.len())?;
+        blocks.append(graph, missing);
+        let pointer = graph.add_trees(tree)[0];
+        Ok(Self::from_parts(graph, blocks, (storer.position, storer.rotation, storer.velocity, storer.angular_velocity, storer.kinematic, storer.body), pointer))
+    }
+
+    /// Same fields as the json format, followed by the tree in its own binary format.
+    pub fn save_binary(&self, graph:&Graph, blocks:&BlockPalette) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.header(ENTITY_MAGIC);
+        self.write_fields(&mut writer);
+        blocks.write_used(&mut writer, &graph.leaves_in(&[self.location.pointer]));
+        graph.write_object(&mut writer, self.location.pointer);
+        writer.finish()
+    }
+
+    pub fn load_binary(graph:&mut Graph, blocks:&mut BlockPalette, data:&[u8]) -> Result<Entity, LoadError> {
+        let mut reader = ByteReader::new(data);
+        let version = reader.header(ENTITY_MAGIC).ok_or(LoadError::Malformed)?;
+        let fields = Self::read_fields(&mut reader, version).ok_or(LoadError::Malformed)?;
+        let used = if version >= 4 { BlockPalette::read_used(&mut reader).ok_or(LoadError::Malformed)? } else { Vec::new() };
+        let (renamed, missing) = blocks.plan_merge(used);
+        let tree = graph.check_object(&mut reader, &renamed, missing.len())?;
+        blocks.append(graph, missing);
+        let pointer = graph.add_trees(tree)[0];
+        Ok(Self::from_parts(graph, blocks, fields, pointer))
+    }
+
+    /// Loads either format, binary saves are recognised by their magic.
+    pub fn load_bytes(graph:&mut Graph, blocks:&mut BlockPalette, data:Vec<u8>) -> Result<Entity, LoadError> {
+        if has_magic(&data, ENTITY_MAGIC) { Self::load_binary(graph, blocks, &data) }
+        else { Self::load(graph, blocks, String::from_utf8(data).map_err(|_| LoadError::Malformed)?) }
+    }
+
+    fn write_fields(&self, writer:&mut ByteWriter) {
+        writer.vec2(self.location.position);
+        writer.f32(self.rotation);
+        writer.vec2(self.velocity);
+        writer.f32(self.angular_velocity);
+        writer.varint(self.kinematic as u64);
+        for coefficient in [self.body.restitution, self.body.friction, self.body.linear_damping, self.body.angular_damping] {
+            writer.f32(coefficient);
+        }
+    }
+
+    fn read_fields(reader:&mut ByteReader, version:u8) -> Option<Fields> {
+        let motion = (reader.vec2()?, reader.f32()?, reader.vec2()?, reader.f32()?);
+        let kinematic = if version >= 2 { reader.varint()? != 0 } else { false };
+        let body = if version >= 3 {
+            Body { restitution: reader.f32()?, friction: reader.f32()?, linear_damping: reader.f32()?, angular_damping: reader.f32()? }
+        } else { Body::default() };
+        Some((motion.0, motion.1, motion.2, motion.3, kinematic, body))
+    }
+
+    fn from_parts(graph:&mut Graph, blocks:&BlockPalette, (position, rotation, velocity, angular_velocity, kinematic, body):Fields, pointer:ExternalPointer) -> Entity {
+        let location = Location::new(position, pointer);
         Entity {
-            id,
+            id: ID::default(),
             location,
-            rotation: storer.rotation,
-            forward: Vec2::from_angle(storer.rotation),
-            velocity: storer.velocity,
-            angular_velocity: storer.angular_velocity,
-            corners: corner_handling::tree_corners(location.pointer, location.min_cell_length),
+            rotation,
+            forward: Vec2::from_angle(rotation),
+            velocity,
+            angular_velocity,
+            corners: corner_handling::tree_corners(graph, blocks, location.pointer, location.min_cell_length),
+            history: EditHistory::new(DEFAULT_HISTORY_DEPTH),
+            kinematic,
+            body,
+            mass: MassProperties::from_tree(graph, blocks, location.pointer, location.min_cell_length),
+            last_position: position,
+            last_rotation: rotation,
         }
     }
 }
@@ -42,5 +197,78 @@ struct EntityStorer {
     rotation: f32,
     velocity: Vec2,
     angular_velocity: f32,
+    #[serde(default)]
+    kinematic: bool,
+    #[serde(default)]
+    body: Body,
+    // What each leaf the tree uses was made of, older saves go by the leaves alone
+    #[serde(default)]
+    blocks: Vec<(Leaf, Block)>,
     graph: String
-}
\ No newline at end of file
+}
+#[test]
+fn saves_carry_their_blocks() {
+    use macroquad::color::{RED, PURPLE};
+    use crate::engine::blocks::CollisionType;
+    let mut source = World::default();
+    // Added in a different order, so the same blocks end up on different leaves
+    let mut target = World::default();
+    let [red, purple] = [RED, PURPLE].map(|color| {
+        source.graph.add_leaf();
+        source.blocks.add(Block::new(color, CollisionType::Solid))
+    });
+    target.graph.add_leaf();
+    let target_purple = target.blocks.add(Block::new(PURPLE, CollisionType::Solid));
+    let mut entity = Entity::blank(&mut source.graph, &source.blocks, Vec2::ZERO, 1);
+    let root = source.graph.get_root(Leaf(0), 1);
+    let root = source.graph.set_node(root, &[0], source.graph.leaf_pointer(red).unwrap()).unwrap();
+    let root = source.graph.set_node(root, &[3], source.graph.leaf_pointer(purple).unwrap()).unwrap();
+    entity.set_root(&mut source.graph, &source.blocks, root);
+    for data in [entity.save_binary(&source.graph, &source.blocks), entity.save(&source.graph, &source.blocks).into_bytes()] {
+        let loaded = Entity::load_bytes(&mut target.graph, &mut target.blocks, data).unwrap();
+        let leaf = |path:&[u32]| target.graph.leaf(target.graph.read(loaded.location.pointer, path).unwrap().pointer).unwrap();
+        // Purple was already there, red is added the first time round and found the second
+        assert_eq!(leaf(&[3]), target_purple);
+        assert_eq!(target.blocks.color(leaf(&[0])), RED);
+        assert_eq!(target.blocks.leaf_count(), 6);
+        assert_eq!(target.graph.leaf_count(), 6);
+    }
+}
+
+#[test]
+fn scenes_keep_their_handles() {
+    use macroquad::color::RED;
+    use crate::engine::blocks::CollisionType;
+    let mut world = World::default();
+    world.graph.add_leaf();
+    let red = world.blocks.add(Block::new(RED, CollisionType::Solid));
+    let spawn = |world:&mut World, x:f32| {
+        let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::new(x, 0.), 0);
+        let root = world.graph.get_root(red, 0);
+        entity.set_root(&mut world.graph, &world.blocks, root);
+        world.entities.spawn(entity)
+    };
+    // A gap and a reused slot, so handles aren't just places
+    let first = spawn(&mut world, 0.);
+    let gone = spawn(&mut world, 1.);
+    let last = spawn(&mut world, 2.);
+    world.entities.despawn(gone);
+    world.entities.despawn(first);
+    let reused = spawn(&mut world, 3.);
+    let saved = world.save_scene(last);
+    spawn(&mut world, 4.);
+
+    let mut fresh = World::default();
+    for world in [&mut world, &mut fresh] {
+        assert_eq!(world.load_scene(&saved).unwrap(), last);
+        assert_eq!(world.entities.len(), 2);
+        assert_eq!(world.entities.get_entity(reused).unwrap().location.position.x, 3.);
+        assert_eq!(world.entities.get_entity(last).unwrap().location.position.x, 2.);
+        assert!(world.entities.get_entity(first).is_none() && world.entities.get_entity(gone).is_none());
+    }
+    // Rejected before the red block's added
+    let mut fresh = World::default();
+    let leaves = (fresh.blocks.leaf_count(), fresh.graph.leaf_count());
+    assert!(fresh.load_scene(&saved[.. saved.len() - 1]).is_err());
+    assert_eq!((fresh.blocks.leaf_count(), fresh.graph.leaf_count()), leaves);
+}
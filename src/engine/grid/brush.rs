@@ -0,0 +1,119 @@
+use std::collections::{HashSet, VecDeque};
+use macroquad::math::{Vec2, UVec2, IVec2};
+use super::dag::{ExternalPointer, GraphNode, SparseDirectedGraph};
+use super::partition::ZorderPath;
+
+// Every brush works on the cells of a grid 2^depth cells wide and returns the paths to write,
+// using the shallowest path possible wherever a whole quadrant is covered.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coverage {
+    Full,
+    Partial,
+    Empty,
+}
+
+/// Splits the grid into the coarsest quadrants which `classify` reports as fully covered.
+/// `classify` is handed the top left cell and side length of a quadrant, and must not return Partial for a single cell.
+pub fn cover(depth:u32, classify:impl Fn(UVec2, u32) -> Coverage) -> Vec<ZorderPath> {
+    let mut stack = vec![ZorderPath::root()];
+    let mut paths = Vec::new();
+    while let Some(path) = stack.pop() {
+        let size = 1 << (depth - path.depth);
+        let top_left = path.to_cell() * size;
+        match classify(top_left, size) {
+            Coverage::Full => paths.push(path),
+            Coverage::Empty => {},
+            Coverage::Partial if path.depth == depth => unreachable!("A single cell can't be partially covered"),
+            Coverage::Partial => for direction in 0 .. 4 { stack.push(path.step_down(direction)) },
+        }
+    }
+    paths
+}
+
+/// Covers every cell between two opposite corners, inclusive.
+pub fn rectangle(corner_a:UVec2, corner_b:UVec2, depth:u32) -> Vec<ZorderPath> {
+    let (min, max) = (corner_a.min(corner_b), corner_a.max(corner_b) + 1);
+    cover(depth, |top_left, size| {
+        let bottom_right = top_left + size;
+        if top_left.cmpge(min).all() && bottom_right.cmple(max).all() { Coverage::Full }
+        else if top_left.cmplt(max).all() && bottom_right.cmpgt(min).all() { Coverage::Partial }
+        else { Coverage::Empty }
+    })
+}
+
+/// Covers every cell whose center lies within radius of center, both measured in cells.
+pub fn circle(center:Vec2, radius:f32, depth:u32) -> Vec<ZorderPath> {
+    cover(depth, |top_left, size| {
+        let first_center = top_left.as_vec2() + 0.5;
+        let last_center = first_center + (size - 1) as f32;
+        let nearest = center.clamp(first_center, last_center);
+        let farthest = Vec2::new(
+            if (center.x - first_center.x).abs() > (center.x - last_center.x).abs() { first_center.x } else { last_center.x },
+            if (center.y - first_center.y).abs() > (center.y - last_center.y).abs() { first_center.y } else { last_center.y },
+        );
+        if farthest.distance(center) <= radius { Coverage::Full }
+        else if nearest.distance(center) <= radius { Coverage::Partial }
+        else { Coverage::Empty }
+    })
+}
+
+/// Bresenham line between two cells, inclusive. Lines are one cell wide so there's nothing to merge.
+pub fn line(start:UVec2, end:UVec2, depth:u32) -> Vec<ZorderPath> {
+    let (mut cur, end) = (start.as_ivec2(), end.as_ivec2());
+    let delta = IVec2::new((end.x - cur.x).abs(), -(end.y - cur.y).abs());
+    let step = (end - cur).signum();
+    let mut error = delta.x + delta.y;
+    let mut paths = vec![ZorderPath::from_cell(cur.as_uvec2(), depth)];
+    while cur != end {
+        let doubled = 2 * error;
+        if doubled >= delta.y { error += delta.y; cur.x += step.x }
+        if doubled <= delta.x { error += delta.x; cur.y += step.y }
+        paths.push(ZorderPath::from_cell(cur.as_uvec2(), depth));
+    }
+    paths
+}
+
+/// Collects every leaf cell connected (edge to edge) to the one containing seed which holds the same leaf.
+/// Seed is a cell at height 0. The paths returned are whole leaves, so the fill never splits a node.
+pub fn flood_fill<T:GraphNode>(graph:&SparseDirectedGraph<T>, root:ExternalPointer, seed:UVec2) -> Vec<ZorderPath> {
+    let leaf_cell = |cell:UVec2| {
+        let path = ZorderPath::from_cell(cell, root.height);
+        let pointer = graph.read(root, &path.steps()).unwrap();
+        (path.with_depth(root.height - pointer.height), pointer.pointer)
+    };
+    let (seed_path, fill_index) = leaf_cell(seed);
+    let mut seen = HashSet::from([seed_path]);
+    let mut queue = VecDeque::from([seed_path]);
+    let mut paths = Vec::new();
+    while let Some(path) = queue.pop_front() {
+        paths.push(path);
+        let size = 1 << (root.height - path.depth);
+        let top_left = (path.to_cell() * size).as_ivec2();
+        // Sample the height 0 cells bordering each edge
+        let mut border = Vec::with_capacity(4 * size as usize);
+        for i in 0 .. size as i32 {
+            border.push(top_left + IVec2::new(i, -1));
+            border.push(top_left + IVec2::new(i, size as i32));
+            border.push(top_left + IVec2::new(-1, i));
+            border.push(top_left + IVec2::new(size as i32, i));
+        }
+        let grid_size = 1 << root.height;
+        for cell in border {
+            if cell.min_element() < 0 || cell.max_element() >= grid_size { continue }
+            let (neighbour, index) = leaf_cell(cell.as_uvec2());
+            if index == fill_index && seen.insert(neighbour) { queue.push_back(neighbour) }
+        }
+    }
+    paths
+}
+
+#[test]
+fn rectangle_merges_covered_quadrants() {
+    // The top half of a 4x4 grid is exactly the two top quadrants
+    let mut paths = rectangle(UVec2::new(3, 1), UVec2::ZERO, 2);
+    paths.sort_by_key(|path| path.zorder);
+    assert_eq!(paths, vec![ZorderPath { zorder: 0, depth: 1 }, ZorderPath { zorder: 1, depth: 1 }]);
+    assert_eq!(rectangle(UVec2::ZERO, UVec2::splat(3), 2), vec![ZorderPath::root()]);
+    assert_eq!(line(UVec2::ZERO, UVec2::new(3, 1), 2).len(), 4);
+}
@@ -1,10 +1,17 @@
 use std::collections::{HashMap, VecDeque};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use vec_mem_heap::prelude::{NodeField, AccessError};
+use super::partition::ZorderPath;
+use super::fold::FoldCaches;
+use crate::engine::binary::{ByteReader, ByteWriter};
 pub use vec_mem_heap::Index;
 
 pub trait GraphNode : Node + std::fmt::Debug + Clone + std::hash::Hash + Eq {}
 
+/// Identifies a leaf independent of where it lives in the graph, palettes are indexed by it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Leaf(pub u16);
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, derive_new::new)]
 pub struct ExternalPointer {
     pub pointer : Index,
@@ -45,24 +52,48 @@ impl<T> Node for vec_mem_heap::internals::MemorySlot<T> where T: GraphNode {
 pub struct SparseDirectedGraph<T: GraphNode> {
     pub nodes : NodeField<T>,
     pub index_lookup : HashMap<T, Index>,
-    leaf_count : u8, 
+    leaves : Vec<Index>,
+    leaf_lookup : HashMap<Index, Leaf>,
+    pub(super) folds : FoldCaches,
 }
 impl<T: GraphNode> SparseDirectedGraph<T> {
     //Utility
-    pub fn new(leaf_count:u8) -> Self {
+    pub fn new(leaf_count:u16) -> Self {
         let mut instance = Self {
             nodes : NodeField::new(),
             index_lookup : HashMap::new(),
-            leaf_count
+            leaves : Vec::new(),
+            leaf_lookup : HashMap::new(),
+            folds : FoldCaches::default(),
         };
-        for i in 0 .. leaf_count {
-            instance.add_node(T::new([Index(i as usize); 4]));
-        }
+        for _ in 0 .. leaf_count { instance.add_leaf(); }
         instance
     }
-    
+
+    /// Leaves are nodes which point to themselves, so we can only build one once we know where it'll be stored.
+    pub fn add_leaf(&mut self) -> Leaf {
+        let leaf = Leaf(self.leaves.len() as u16);
+        let index = self.nodes.push(T::new([Index(0); 4]));
+        let node = T::new([index; 4]);
+        self.nodes.replace(index, node.clone()).unwrap();
+        self.index_lookup.insert(node, index);
+        self.leaves.push(index);
+        self.leaf_lookup.insert(index, leaf);
+        leaf
+    }
+
+    pub fn leaf_count(&self) -> usize { self.leaves.len() }
+
     pub fn is_leaf(&self, index:Index) -> bool {
-        *index < self.leaf_count as usize
+        self.leaf_lookup.contains_key(&index)
+    }
+
+    pub fn leaf(&self, index:Index) -> Option<Leaf> {
+        self.leaf_lookup.get(&index).copied()
+    }
+
+    pub fn leaf_pointer(&self, leaf:Leaf) -> Option<Index> {
+        self.leaves.get(leaf.0 as usize).copied()
     }
 
     fn get_trail(&self, start:Index, path:&[u32]) -> Vec<Index>  {
@@ -89,6 +120,13 @@ impl<T: GraphNode> SparseDirectedGraph<T> {
         index
     }
 
+    fn find_or_add(&mut self, node:T) -> Index {
+        match self.find_index(&node) {
+            Some(index) => index,
+            None => self.add_node(node),
+        }
+    }
+
     fn propagate_change(
         &mut self,
         path: &[u32],
@@ -130,14 +168,15 @@ impl<T: GraphNode> SparseDirectedGraph<T> {
             ExternalPointer::new(new_pointer, start.height - path.len() as u32),
             start.pointer,
         )?;
-        let last_leaf = self.leaf_count as usize - 1;
-        let old_nodes = bfs_nodes(self.nodes.internal_memory(), old_parent, last_leaf); 
+        let old_nodes = bfs_nodes(self.nodes.internal_memory(), old_parent); 
         let early_exit = match early_node { Some(node) => {
+            // Everything above the rewritten node keeps its index but not its contents
+            for index in &trail { self.folds.forget(*index) }
             self.index_lookup.remove(&self.nodes.replace(old_parent, node.clone()).unwrap());
             self.index_lookup.insert(node, old_parent);
             true
         } None => { false }};
-        for index in bfs_nodes(self.nodes.internal_memory(), cur_pointer.pointer, last_leaf) {
+        for index in bfs_nodes(self.nodes.internal_memory(), cur_pointer.pointer) {
             self.nodes.add_ref(index).unwrap()
         }
         self.mass_remove(&old_nodes);
@@ -145,10 +184,60 @@ impl<T: GraphNode> SparseDirectedGraph<T> {
         if early_exit { Ok(start) } else { Ok(cur_pointer) }
     }
 
+    /// Applies every write in a single bottom-up pass, refcounting the old and new trees once.
+    /// Paths are relative to start and writes are applied in order, so later writes win where they overlap.
+    pub fn set_nodes(&mut self, start:ExternalPointer, writes:&[(ZorderPath, Index)]) -> Result<ExternalPointer, AccessError> {
+        if writes.iter().any(|(path, _)| path.depth > start.height) { return Err(AccessError::OperationFailed) }
+        let pending:Vec<usize> = (0 .. writes.len()).collect();
+        let new_root = self.rebuild(start.pointer, 0, writes, &pending)?;
+        if new_root == start.pointer { return Ok(start) }
+        self.add_tree_ref(new_root);
+        self.remove_tree_ref(start.pointer);
+        Ok(ExternalPointer::new(new_root, start.height))
+    }
+
+    // pending holds the writes (by position in writes) which land somewhere within node, in the order they were given.
+    fn rebuild(&mut self, mut node:Index, depth:u32, writes:&[(ZorderPath, Index)], pending:&[usize]) -> Result<Index, AccessError> {
+        // A write to this node replaces it and everything queued before it
+        let pending = match pending.iter().rposition(|write| writes[*write].0.depth == depth) {
+            Some(last) => {
+                node = writes[pending[last]].1;
+                &pending[last + 1 ..]
+            }
+            None => pending
+        };
+        if pending.is_empty() { return Ok(node) }
+        let mut quadrants:[Vec<usize>; 4] = Default::default();
+        for write in pending {
+            quadrants[writes[*write].0.read_step(depth + 1) as usize].push(*write);
+        }
+        // Leaves are their own children, so writing into one splits it for free
+        let mut children = self.node(node)?.children();
+        for (child, queued) in quadrants.iter().enumerate() {
+            if !queued.is_empty() {
+                children[child] = self.rebuild(children[child], depth + 1, writes, queued)?;
+            }
+        }
+        Ok(self.find_or_add(T::new(children)))
+    }
+
+    /// Adds a reference along every path of the tree, which is what holding a root means.
+    pub fn add_tree_ref(&mut self, root:Index) {
+        for index in bfs_nodes(self.nodes.internal_memory(), root) {
+            self.nodes.add_ref(index).unwrap()
+        }
+    }
+
+    /// Drops a reference taken by add_tree_ref (or handed out by a write), freeing whatever is left unreferenced.
+    pub fn remove_tree_ref(&mut self, root:Index) {
+        self.mass_remove(&bfs_nodes(self.nodes.internal_memory(), root));
+    }
+
     pub fn mass_remove(&mut self, indices:&[Index]) {
         for index in indices {
             self.nodes.remove_ref(*index).unwrap();
             if self.nodes.status(*index).unwrap().get() == 1 && !self.is_leaf(*index) {
+                self.folds.forget(*index);
                 self.index_lookup.remove(&self.nodes.remove_ref(*index).unwrap().unwrap());
             }
         }
@@ -169,41 +258,318 @@ impl<T: GraphNode> SparseDirectedGraph<T> {
         Some(ExternalPointer::new(*node_pointer, start.height - (trail.len() as u32 - 1)))
     }
 
-    pub fn get_root(&mut self, leaf:usize, height:u32) -> ExternalPointer {
-        self.nodes.add_ref(Index(leaf)).unwrap();
-        ExternalPointer::new(Index(leaf), height)
+    pub fn get_root(&mut self, leaf:Leaf, height:u32) -> ExternalPointer {
+        let index = self.leaf_pointer(leaf).unwrap();
+        self.nodes.add_ref(index).unwrap();
+        ExternalPointer::new(index, height)
     }
 
+    //Maintenance
+    /// Rebuilds the graph from `roots` alone, storing every reachable node densely behind the leaves.
+    /// Anything not reachable from a root is dropped, so every live root must be passed in.
+    /// Returns a table of old -> new indices which callers use to patch the roots they hold.
+    pub fn compact(&mut self, roots:&[Index]) -> HashMap<Index, Index> {
+        let mut compacted = Self::new(self.leaf_count() as u16);
+        let mut remapped:HashMap<Index, Index> = self.leaves.iter().copied().zip(compacted.leaves.iter().copied()).collect();
+        for root in roots {
+            compacted.clone_nodes(self.nodes.internal_memory(), *root, &mut remapped);
+        }
+        *self = compacted;
+        remapped
+    }
+
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Json(serde_json::Error),
+    // Binary data that ran out early, had the wrong header or didn't match the layout
+    Malformed,
+    DanglingRoot { root: usize, node_count: usize },
+    DanglingChild { node: usize, child: usize },
+    Cycle { node: usize },
+    // The save uses a leaf this graph doesn't have
+    LeafMismatch { leaf: Leaf, leaf_count: usize },
+    // The tree is deeper than its root's height allows
+    HeightMismatch { height: u32, depth: u32 },
+}
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json(error) => write!(f, "invalid json, {error}"),
+            Self::Malformed => write!(f, "malformed or truncated data"),
+            Self::DanglingRoot { root, node_count } => write!(f, "root {root} is outside the {node_count} stored nodes"),
+            Self::DanglingChild { node, child } => write!(f, "node {node} points to missing child {child}"),
+            Self::Cycle { node } => write!(f, "node {node} is its own ancestor"),
+            Self::LeafMismatch { leaf, leaf_count } => write!(f, "leaf {} doesn't exist, the graph only has {leaf_count}", leaf.0),
+            Self::HeightMismatch { height, depth } => write!(f, "tree is {depth} layers deep but its root is at height {height}"),
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+impl From<serde_json::Error> for LoadError {
+    fn from(error: serde_json::Error) -> Self { Self::Json(error) }
 }
 
 #[derive(Serialize, Deserialize)]
 struct TreeStorage<T : GraphNode> {
     root: ExternalPointer,
+    // The first leaves.len() nodes are the leaves, in this order
+    #[serde(default)]
+    leaves: Vec<Leaf>,
+    nodes: Vec<T>,
+}
+impl<T : GraphNode> TreeStorage<T> {
+    // Saves from before leaves were recorded store every leaf of the graph at its own index.
+    fn stored_leaves(&self) -> Vec<Leaf> {
+        if !self.leaves.is_empty() { return self.leaves.clone() }
+        self.nodes.iter().enumerate()
+            .take_while(|(index, node)| node.children() == [Index(*index); 4])
+            .map(|(index, _)| Leaf(index as u16))
+            .collect()
+    }
+
+    fn write(&self, writer:&mut ByteWriter) {
+        writer.header(TREE_MAGIC);
+        write_leaves(writer, &self.leaves);
+        write_root(writer, self.root);
+        write_branches(writer, &self.nodes[self.leaves.len() ..]);
+    }
+
+    fn read(reader:&mut ByteReader) -> Option<Self> {
+        reader.header(TREE_MAGIC)?;
+        let (leaves, mut nodes) = read_leaves(reader)?;
+        let root = read_root(reader)?;
+        read_branches(reader, &mut nodes)?;
+        Some(Self { root, leaves, nodes })
+    }
+}
+const TREE_MAGIC: &[u8; 4] = b"GGTR";
+
+/// Trees read from a save and checked, so adding them can't fail once the graph has every leaf they were checked against.
+pub struct CheckedTrees<T : GraphNode> {
     nodes: Vec<T>,
+    leaves: Vec<Leaf>,
+    roots: Vec<ExternalPointer>,
+}
+impl<T : GraphNode> CheckedTrees<T> {
+    pub fn len(&self) -> usize { self.roots.len() }
+}
+
+// Several trees sharing one node table, so subtrees common to them are only stored once.
+struct ForestStorage<T : GraphNode> {
+    roots: Vec<ExternalPointer>,
+    leaves: Vec<Leaf>,
+    nodes: Vec<T>,
+}
+impl<T : GraphNode> ForestStorage<T> {
+    fn write(&self, writer:&mut ByteWriter) {
+        writer.header(FOREST_MAGIC);
+        write_leaves(writer, &self.leaves);
+        writer.varint(self.roots.len() as u64);
+        for root in &self.roots { write_root(writer, *root) }
+        write_branches(writer, &self.nodes[self.leaves.len() ..]);
+    }
+
+    fn read(reader:&mut ByteReader) -> Option<Self> {
+        reader.header(FOREST_MAGIC)?;
+        let (leaves, mut nodes) = read_leaves(reader)?;
+        let mut roots = Vec::new();
+        for _ in 0 .. reader.varint()? { roots.push(read_root(reader)?) }
+        read_branches(reader, &mut nodes)?;
+        Some(Self { roots, leaves, nodes })
+    }
+}
+const FOREST_MAGIC: &[u8; 4] = b"GGFR";
+
+// Leaves are implied by the leaf table, so only the branches are written
+fn write_leaves(writer:&mut ByteWriter, leaves:&[Leaf]) {
+    writer.varint(leaves.len() as u64);
+    for leaf in leaves { writer.varint(leaf.0 as u64) }
+}
+
+fn write_root(writer:&mut ByteWriter, root:ExternalPointer) {
+    writer.varint(*root.pointer as u64);
+    writer.varint(root.height as u64);
+}
+
+fn write_branches<T : GraphNode>(writer:&mut ByteWriter, branches:&[T]) {
+    writer.varint(branches.len() as u64);
+    for node in branches {
+        for child in node.children() { writer.varint(*child as u64) }
+    }
 }
-//Assumes constant leaf count. Eventually add more metadata
+
+// Returns the leaves along with the self-referencing nodes standing in for them
+fn read_leaves<T : GraphNode>(reader:&mut ByteReader) -> Option<(Vec<Leaf>, Vec<T>)> {
+    let leaf_count = reader.varint()? as usize;
+    let mut leaves = Vec::new();
+    for _ in 0 .. leaf_count { leaves.push(Leaf(u16::try_from(reader.varint()?).ok()?)) }
+    let nodes = (0 .. leaf_count).map(|index| T::new([Index(index); 4])).collect();
+    Some((leaves, nodes))
+}
+
+fn read_root(reader:&mut ByteReader) -> Option<ExternalPointer> {
+    Some(ExternalPointer::new(Index(reader.varint()? as usize), u32::try_from(reader.varint()?).ok()?))
+}
+
+fn read_branches<T : GraphNode>(reader:&mut ByteReader, nodes:&mut Vec<T>) -> Option<()> {
+    for _ in 0 .. reader.varint()? {
+        let mut children = [Index(0); 4];
+        for child in children.iter_mut() { *child = Index(reader.varint()? as usize) }
+        nodes.push(T::new(children));
+    }
+    Some(())
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+    /// Every leaf the trees use, in the order saves store them.
+    pub fn leaves_in(&self, starts:&[ExternalPointer]) -> Vec<Leaf> {
+        let mut leaves:Vec<Leaf> = starts.iter()
+            .flat_map(|start| bfs_nodes(self.nodes.internal_memory(), start.pointer))
+            .filter_map(|index| self.leaf(index)).collect();
+        leaves.sort();
+        leaves.dedup();
+        leaves
+    }
+
+    // Copies the trees into a fresh graph holding only the leaves they use, so the stored indices are dense.
+    fn forest_storage(&self, starts:&[ExternalPointer]) -> ForestStorage<T> {
+        let leaves = self.leaves_in(starts);
+        let mut object_graph = Self::new(leaves.len() as u16);
+        let mut remapped = HashMap::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            remapped.insert(self.leaf_pointer(*leaf).unwrap(), object_graph.leaves[i]);
+        }
+        let roots = starts.iter().map(|start| {
+            ExternalPointer::new(object_graph.clone_nodes(self.nodes.internal_memory(), start.pointer, &mut remapped), start.height)
+        }).collect();
+        ForestStorage {
+            roots,
+            leaves,
+            nodes : object_graph.nodes.internal_memory().iter().map(|node| T::new(node.children())).collect(), 
+        }
+    }
+
+    fn object_storage(&self, start:ExternalPointer) -> TreeStorage<T> {
+        let ForestStorage { roots, leaves, nodes } = self.forest_storage(&[start]);
+        TreeStorage { root: roots[0], leaves, nodes }
+    }
+
+    //Leaves are matched up by their Leaf, not by where they were stored.
+    fn check_tree(&self, storage:TreeStorage<T>, renamed:&HashMap<Leaf, Leaf>, pending:usize) -> Result<CheckedTrees<T>, LoadError> {
+        let leaves = rename_leaves(&storage.stored_leaves(), renamed);
+        self.check_storage(storage.nodes, leaves, vec![storage.root], pending)
+    }
+
+    // Everything clone_graph would otherwise panic on, checked before the graph is touched
+    fn check_storage(&self, nodes:Vec<T>, leaves:Vec<Leaf>, roots:Vec<ExternalPointer>, pending:usize) -> Result<CheckedTrees<T>, LoadError> {
+        if leaves.len() > nodes.len() { return Err(LoadError::Malformed) }
+        let leaf_count = self.leaf_count() + pending;
+        for (index, leaf) in leaves.iter().enumerate() {
+            if nodes[index].children() != [Index(index); 4] { return Err(LoadError::Malformed) }
+            if leaf.0 as usize >= leaf_count { return Err(LoadError::LeafMismatch { leaf: *leaf, leaf_count }) }
+        }
+        // Number of branches between each finished node and its deepest leaf
+        let mut depths:Vec<Option<u32>> = (0 .. nodes.len()).map(|index| (index < leaves.len()).then_some(0)).collect();
+        let mut on_path = vec![false; nodes.len()];
+        for root in &roots {
+            if *root.pointer >= nodes.len() { return Err(LoadError::DanglingRoot { root: *root.pointer, node_count: nodes.len() }) }
+            let mut stack = vec![*root.pointer];
+            while let Some(&node) = stack.last() {
+                if depths[node].is_some() { stack.pop(); continue }
+                on_path[node] = true;
+                let children = nodes[node].children();
+                let mut finished = true;
+                for child in children.map(|child| *child) {
+                    if child >= nodes.len() { return Err(LoadError::DanglingChild { node, child }) }
+                    if depths[child].is_none() {
+                        if on_path[child] { return Err(LoadError::Cycle { node: child }) }
+                        stack.push(child);
+                        finished = false;
+                    }
+                }
+                if finished {
+                    depths[node] = Some(1 + children.iter().map(|child| depths[**child].unwrap()).max().unwrap());
+                    on_path[node] = false;
+                    stack.pop();
+                }
+            }
+            let depth = depths[*root.pointer].unwrap();
+            if depth > root.height { return Err(LoadError::HeightMismatch { height: root.height, depth }) }
+        }
+        Ok(CheckedTrees { nodes, leaves, roots })
+    }
+
+    /// Adds trees which have already been checked, once the leaves they were checked with are all in the graph.
+    pub fn add_trees(&mut self, trees:CheckedTrees<T>) -> Vec<ExternalPointer> {
+        trees.roots.iter().map(|root| {
+            ExternalPointer::new(self.clone_graph(&trees.nodes, root.pointer, &trees.leaves), root.height)
+        }).collect()
+    }
+
+    pub fn write_object(&self, writer:&mut ByteWriter, start:ExternalPointer) {
+        self.object_storage(start).write(writer)
+    }
+
+    /// Writes every tree into one shared node table, roots come back from read_forest in the same order.
+    pub fn write_forest(&self, writer:&mut ByteWriter, starts:&[ExternalPointer]) {
+        self.forest_storage(starts).write(writer)
+    }
+
+    /// Nothing is added to the graph unless every tree is valid.
+    /// Saved leaves found in renamed are loaded as what they map to, the rest as themselves.
+    pub fn read_forest(&mut self, reader:&mut ByteReader, renamed:&HashMap<Leaf, Leaf>) -> Result<Vec<ExternalPointer>, LoadError> {
+        let trees = self.check_forest(reader, renamed, 0)?;
+        Ok(self.add_trees(trees))
+    }
+
+    /// Reads and checks a forest without touching the graph, pending is how many leaves will be added before it is.
+    pub fn check_forest(&self, reader:&mut ByteReader, renamed:&HashMap<Leaf, Leaf>, pending:usize) -> Result<CheckedTrees<T>, LoadError> {
+        let storage = ForestStorage::<T>::read(reader).ok_or(LoadError::Malformed)?;
+        let leaves = rename_leaves(&storage.leaves, renamed);
+        self.check_storage(storage.nodes, leaves, storage.roots, pending)
+    }
+
+    /// Reads and checks a single tree, like check_forest.
+    pub fn check_object(&self, reader:&mut ByteReader, renamed:&HashMap<Leaf, Leaf>, pending:usize) -> Result<CheckedTrees<T>, LoadError> {
+        let storage = TreeStorage::read(reader).ok_or(LoadError::Malformed)?;
+        self.check_tree(storage, renamed, pending)
+    }
+}
+
+fn rename_leaves(leaves:&[Leaf], renamed:&HashMap<Leaf, Leaf>) -> Vec<Leaf> {
+    leaves.iter().map(|leaf| *renamed.get(leaf).unwrap_or(leaf)).collect()
+}
+
 impl<T: GraphNode + Serialize + DeserializeOwned> SparseDirectedGraph<T> {
     pub fn save_object_json(&self, start:ExternalPointer) -> String {
-        let mut object_graph = Self::new(self.leaf_count);
-        let root_index = object_graph.clone_graph(self.nodes.internal_memory(), start.pointer);
-        serde_json::to_string(&TreeStorage {
-            root : ExternalPointer::new(root_index, start.height),
-            nodes : object_graph.nodes.internal_memory().iter().map(|node| T::new(node.children())).collect(), 
-        }).unwrap()
+        serde_json::to_string(&self.object_storage(start)).unwrap()
     }
     
     //Currently requires the nodetype of both graph and data to be the same.
-    pub fn load_object_json(&mut self, json:String) -> ExternalPointer {
-        let temp:TreeStorage<T> = serde_json::from_str(&json).unwrap();
-        ExternalPointer::new(self.clone_graph(&temp.nodes, temp.root.pointer), temp.root.height)
+    pub fn check_object_json(&self, json:String, renamed:&HashMap<Leaf, Leaf>, pending:usize) -> Result<CheckedTrees<T>, LoadError> {
+        let temp:TreeStorage<T> = serde_json::from_str(&json)?;
+        self.check_tree(temp, renamed, pending)
     }
 
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
     // Clippy thinks I should pass a slice here instead of a vector, but passing a partial slice is very likely to lead to operation failure
-    //Assumes equal leaf count (between the two graphs)
-    fn clone_graph<N : Node> (&mut self, from:&Vec<N>, start:Index) -> Index {
+    //Assumes the first leaves.len() nodes of from are the leaves
+    fn clone_graph<N : Node> (&mut self, from:&Vec<N>, start:Index, leaves:&[Leaf]) -> Index {
         let mut remapped = HashMap::new();
-        for i in 0 .. self.leaf_count as usize { remapped.insert(Index(i), Index(i)); }
-        for pointer in bfs_nodes(from, start, self.leaf_count as usize - 1).into_iter().rev() {
+        for (i, leaf) in leaves.iter().enumerate() {
+            remapped.insert(Index(i), self.leaf_pointer(*leaf).unwrap());
+        }
+        self.clone_nodes(from, start, &mut remapped)
+    }
+
+    // Shared between saving, loading and compaction. remapped must already map every leaf of from,
+    // and carries over between calls so shared subtrees are only cloned once.
+    fn clone_nodes<N : Node> (&mut self, from:&Vec<N>, start:Index, remapped:&mut HashMap<Index, Index>) -> Index {
+        for pointer in bfs_nodes(from, start).into_iter().rev() {
             if !remapped.contains_key(&pointer) {
                 let old_kids = &from[*pointer].children();
                 let new_node = T::new([
@@ -212,23 +578,109 @@ impl<T: GraphNode + Serialize + DeserializeOwned> SparseDirectedGraph<T> {
                     *remapped.get(&old_kids[2]).unwrap(),
                     *remapped.get(&old_kids[3]).unwrap()
                 ]);
-                remapped.insert(pointer, self.add_node(new_node));
+                // The node may already exist if we're cloning into a populated graph
+                remapped.insert(pointer, self.find_or_add(new_node));
             }
             self.nodes.add_ref(*remapped.get(&pointer).unwrap()).unwrap();
         }
         *remapped.get(&start).unwrap()
     }
-
 }
 
-// Assumes leaves are stored contiguously at the front of the slice.
-pub fn bfs_nodes<N: Node>(nodes:&Vec<N>, start:Index, last_leaf:usize) -> Vec<Index> {
+// Leaves are the only nodes which are their own children, so we stop there.
+pub fn bfs_nodes<N: Node>(nodes:&Vec<N>, start:Index) -> Vec<Index> {
     let mut queue = VecDeque::from([start]);
     let mut bfs_indexes = Vec::new();
     while let Some(index) = queue.pop_front() {
         bfs_indexes.push(index);
-        if *index > last_leaf { queue.extend(nodes[*index].children()) }
+        let children = nodes[*index].children();
+        if children[0] != index { queue.extend(children) }
     }
     bfs_indexes
 }
 
+
+#[test]
+fn compact_drops_dead_nodes() {
+    let mut graph = SparseDirectedGraph::<BasicNode>::new(4);
+    let scratch = graph.get_root(Leaf(0), 2);
+    let scratch = graph.set_node(scratch, &[3, 3], Index(2)).unwrap();
+    let root = graph.get_root(Leaf(0), 3);
+    let root = graph.set_node(root, &[0, 1, 2], Index(1)).unwrap();
+    let dead = bfs_nodes(graph.nodes.internal_memory(), scratch.pointer);
+    graph.mass_remove(&dead);
+    let remapped = graph.compact(&[root.pointer]);
+    let root = ExternalPointer::new(remapped[&root.pointer], root.height);
+    // Four leaves and the three nodes along the path
+    assert_eq!(graph.nodes.internal_memory().len(), 7);
+    assert_eq!(graph.read(root, &[0, 1, 2]).unwrap().pointer, Index(1));
+    assert_eq!(graph.read(root, &[3]).unwrap().pointer, Index(0));
+}
+
+#[test]
+fn batched_writes_match_sequential_writes() {
+    let mut graph = SparseDirectedGraph::<BasicNode>::new(4);
+    let writes = [
+        (ZorderPath::from_cell(macroquad::math::UVec2::new(1, 2), 3), Index(1)),
+        (ZorderPath::from_cell(macroquad::math::UVec2::new(6, 6), 3), Index(2)),
+        (ZorderPath::from_cell(macroquad::math::UVec2::new(0, 1), 2), Index(3)),
+        (ZorderPath::from_cell(macroquad::math::UVec2::new(0, 3), 3), Index(1)),
+    ];
+    let mut sequential = graph.get_root(Leaf(0), 3);
+    for (path, index) in writes {
+        sequential = graph.set_node(sequential, &path.steps(), index).unwrap();
+    }
+    let batched = graph.get_root(Leaf(0), 3);
+    let batched = graph.set_nodes(batched, &writes).unwrap();
+    assert_eq!(batched.pointer, sequential.pointer);
+}
+
+#[test]
+fn binary_trees_round_trip() {
+    let mut graph = SparseDirectedGraph::<BasicNode>::new(4);
+    let root = graph.get_root(Leaf(0), 3);
+    let root = graph.set_node(root, &[0, 1, 2], Index(3)).unwrap();
+    let root = graph.set_node(root, &[2], Index(1)).unwrap();
+    let mut other = SparseDirectedGraph::<BasicNode>::new(4);
+    let mut writer = ByteWriter::new();
+    graph.write_object(&mut writer, root);
+    let bytes = writer.finish();
+    let trees = other.check_object(&mut ByteReader::new(&bytes), &HashMap::new(), 0).unwrap();
+    let loaded = other.add_trees(trees)[0];
+    assert_eq!(loaded.height, root.height);
+    assert_eq!(other.save_object_json(loaded), graph.save_object_json(root));
+}
+
+#[test]
+fn forests_share_subtrees() {
+    let mut graph = SparseDirectedGraph::<BasicNode>::new(2);
+    let root = graph.get_root(Leaf(0), 2);
+    let a = graph.set_node(root, &[0, 1], Index(1)).unwrap();
+    // Held so the next edit can't happen in place
+    graph.add_tree_ref(a.pointer);
+    let b = graph.set_node(a, &[3], Index(1)).unwrap();
+    // b only adds one branch on top of a's, everything else is shared
+    assert_eq!(graph.forest_storage(&[a, b]).nodes.len(), graph.object_storage(a).nodes.len() + 1);
+    let mut writer = ByteWriter::new();
+    graph.write_forest(&mut writer, &[a, b]);
+    let bytes = writer.finish();
+    let mut other = SparseDirectedGraph::<BasicNode>::new(2);
+    let loaded = other.read_forest(&mut ByteReader::new(&bytes), &HashMap::new()).unwrap();
+    assert_eq!(other.save_object_json(loaded[1]), graph.save_object_json(b));
+}
+
+#[test]
+fn corrupt_trees_are_rejected() {
+    let graph = SparseDirectedGraph::<BasicNode>::new(2);
+    let load = |graph:&SparseDirectedGraph<BasicNode>, height:u32, nodes:&str| {
+        graph.check_object_json(format!(r#"{{"root":{{"pointer":2,"height":{height}}},"leaves":[0,1],"nodes":[{nodes}]}}"#), &HashMap::new(), 0)
+    };
+    let leaves = r#"{"children":[0,0,0,0]},{"children":[1,1,1,1]}"#;
+    assert!(load(&graph, 1, &format!(r#"{leaves},{{"children":[0,1,0,1]}}"#)).is_ok());
+    assert!(matches!(load(&graph, 1, &format!(r#"{leaves},{{"children":[0,7,0,1]}}"#)), Err(LoadError::DanglingChild { node: 2, child: 7 })));
+    assert!(matches!(load(&graph, 2, &format!(r#"{leaves},{{"children":[0,3,0,1]}},{{"children":[2,0,0,0]}}"#)), Err(LoadError::Cycle { .. })));
+    assert!(matches!(load(&graph, 0, &format!(r#"{leaves},{{"children":[0,1,0,1]}}"#)), Err(LoadError::HeightMismatch { height: 0, depth: 1 })));
+    assert!(matches!(graph.check_object_json("{".to_string(), &HashMap::new(), 0), Err(LoadError::Json(_))));
+    let small = SparseDirectedGraph::<BasicNode>::new(1);
+    assert!(matches!(load(&small, 1, &format!(r#"{leaves},{{"children":[0,1,0,1]}}"#)), Err(LoadError::LeafMismatch { .. })));
+}
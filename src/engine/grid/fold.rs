@@ -0,0 +1,76 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use super::dag::{GraphNode, Index, Leaf, SparseDirectedGraph};
+
+/// A value computed for every node from its children's, like how much of a subtree is solid.
+/// Nodes are shared, so the value can only depend on the node itself and not where it sits in a tree.
+/// One cache is kept per fold type, so every instance of a fold has to agree on what it computes.
+pub trait NodeFold : 'static {
+    type Value : Clone + Send + Sync + 'static;
+    fn leaf(&self, leaf:Leaf) -> Self::Value;
+    fn branch(&self, children:[Self::Value; 4]) -> Self::Value;
+}
+
+trait FoldCache : Send + Sync {
+    fn forget(&mut self, index:Index);
+    fn as_any(&mut self) -> &mut dyn Any;
+}
+impl<V: Send + Sync + 'static> FoldCache for HashMap<Index, V> {
+    fn forget(&mut self, index:Index) { self.remove(&index); }
+    fn as_any(&mut self) -> &mut dyn Any { self }
+}
+
+/// Every fold's values by node, the graph forgets a node's values when it frees or rewrites it.
+#[derive(Default)]
+pub struct FoldCaches(HashMap<TypeId, Box<dyn FoldCache>>);
+impl FoldCaches {
+    pub fn forget(&mut self, index:Index) {
+        for cache in self.0.values_mut() { cache.forget(index) }
+    }
+}
+
+impl<T: GraphNode> SparseDirectedGraph<T> {
+    /// Folds the tree under root bottom up, only visiting nodes no earlier fold has seen.
+    pub fn fold<F: NodeFold>(&mut self, fold:&F, root:Index) -> F::Value {
+        let mut cache = self.folds.0.remove(&TypeId::of::<F>())
+            .unwrap_or_else(|| Box::new(HashMap::<Index, F::Value>::new()));
+        let value = self.fold_node(fold, cache.as_any().downcast_mut().unwrap(), root);
+        self.folds.0.insert(TypeId::of::<F>(), cache);
+        value
+    }
+
+    fn fold_node<F: NodeFold>(&self, fold:&F, cache:&mut HashMap<Index, F::Value>, index:Index) -> F::Value {
+        if let Some(value) = cache.get(&index) { return value.clone() }
+        let value = match self.leaf(index) {
+            Some(leaf) => fold.leaf(leaf),
+            None => {
+                let children = self.node(index).unwrap().children();
+                fold.branch(children.map(|child| self.fold_node(fold, cache, child)))
+            }
+        };
+        cache.insert(index, value.clone());
+        value
+    }
+}
+
+#[test]
+fn folds_follow_edits() {
+    use super::dag::BasicNode;
+    // How much of a node is leaf 1
+    struct Solid;
+    impl NodeFold for Solid {
+        type Value = f32;
+        fn leaf(&self, leaf:Leaf) -> f32 { if leaf == Leaf(1) { 1. } else { 0. } }
+        fn branch(&self, children:[f32; 4]) -> f32 { children.iter().sum::<f32>() / 4. }
+    }
+    let mut graph = SparseDirectedGraph::<BasicNode>::new(2);
+    let root = graph.get_root(Leaf(0), 2);
+    let root = graph.set_node(root, &[0, 3], Index(1)).unwrap();
+    assert_eq!(graph.fold(&Solid, root.pointer) * 16., 1.);
+    // Written in place, so the root keeps its index and only invalidation keeps the fold honest
+    let edited = graph.set_node(root, &[0, 0], Index(1)).unwrap();
+    assert_eq!(edited.pointer, root.pointer);
+    assert_eq!(graph.fold(&Solid, edited.pointer) * 16., 2.);
+    let filled = graph.set_node(edited, &[1], Index(1)).unwrap();
+    assert_eq!(graph.fold(&Solid, filled.pointer) * 16., 6.);
+}
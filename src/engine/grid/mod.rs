@@ -1,3 +1,5 @@
 pub mod dag;
 pub mod partition;
+pub mod brush;
+pub mod fold;
 
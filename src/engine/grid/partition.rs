@@ -1,13 +1,13 @@
 use macroquad::math::{Vec2, IVec2, UVec2};
 use crate::engine::math::{Aabb, FloatUtils};
-use crate::engine::grid::dag::ExternalPointer;
+use crate::engine::grid::dag::{ExternalPointer, Leaf, GraphNode, SparseDirectedGraph};
 use crate::engine::entities::Location;
-use crate::globals::GRAPH;
+use crate::engine::blocks::BlockPalette;
 //Value loosely tuned to prevent both phasing and catching on corners
 //Used to sample area around a point to determine what cell(s) it's in
 pub const LIM_OFFSET: f32 = 2. / 0xFFFF as f32;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ZorderPath {
     pub zorder : u32,
     pub depth : u32
@@ -86,9 +86,29 @@ impl ZorderPath {
         steps
     }
 
-    #[allow(dead_code)]
-    pub fn cells_intersecting_aabb(_aabb:Aabb, _max_depth: u32) -> Vec<Self> {
-        todo!()
+    /// Walks the tree from start and returns the coarsest leaf cells overlapping aabb.
+    /// aabb is in the grid's local space, where the top left of the root is (0, 0).
+    /// Subtrees outside of aabb are never entered and cells the palette calls empty are skipped.
+    pub fn cells_intersecting_aabb<T:GraphNode>(graph:&SparseDirectedGraph<T>, blocks:&BlockPalette, start:ExternalPointer, aabb:Aabb, min_cell_length:Vec2) -> Vec<CellData> {
+        let mut stack = Vec::from([(start.pointer, Self::root())]);
+        let mut cells = Vec::new();
+        while let Some((pointer, zorder)) = stack.pop() {
+            let height = start.height - zorder.depth;
+            let length = cell_length(height, min_cell_length);
+            let top_left = zorder.to_cell().as_vec2() * length;
+            // Touching an edge doesn't count as overlapping
+            if !(top_left.less(aabb.max()) & (top_left + length).greater(aabb.min())).all() { continue }
+            match graph.leaf(pointer) {
+                Some(leaf) if blocks.is_empty_leaf(leaf) => {},
+                Some(leaf) => cells.push(CellData::new(ExternalPointer::new(pointer, height), zorder.to_cell(), leaf)),
+                None => {
+                    for (i, child) in graph.node(pointer).unwrap().children().into_iter().enumerate() {
+                        stack.push((child, zorder.step_down(i as u32)))
+                    }
+                }
+            }
+        }
+        cells
     }
 
 }
@@ -97,6 +117,7 @@ impl ZorderPath {
 pub struct CellData {
     pub pointer : ExternalPointer,
     pub cell : UVec2,
+    pub leaf : Leaf,
 }
 impl CellData {
     pub fn bound_data(&self) -> (Vec2, u32) { (self.cell.as_vec2(), self.pointer.height) }
@@ -138,23 +159,23 @@ pub mod gate {
         surrounding
     }
     
-    pub fn point_to_real_cells(location:Location, point:Vec2) -> [Option<CellData>; 4] {
+    pub fn point_to_real_cells<T:GraphNode>(graph:&SparseDirectedGraph<T>, location:Location, point:Vec2) -> [Option<CellData>; 4] {
         let mut surrounding = [None; 4];
         let cells = point_to_cells(location, 0, point);
         for i in 0..4 {
             if let Some(cell) = cells[i] {
-                surrounding[i] = Some(find_real_cell(location.pointer, cell));
+                surrounding[i] = Some(find_real_cell(graph, location.pointer, cell));
             }
         }
         surrounding
     }
     
     /// Only works if cell is at height 0
-    pub fn find_real_cell(start:ExternalPointer, cell:UVec2) -> CellData {
+    pub fn find_real_cell<T:GraphNode>(graph:&SparseDirectedGraph<T>, start:ExternalPointer, cell:UVec2) -> CellData {
         let path = ZorderPath::from_cell(cell, start.height);
-        let pointer = GRAPH.read().read(start, &path.steps()).unwrap();
+        let pointer = graph.read(start, &path.steps()).unwrap();
         let zorder = path.with_depth(start.height - pointer.height);
-        CellData::new(pointer, zorder.to_cell())
+        CellData::new(pointer, zorder.to_cell(), graph.leaf(pointer.pointer).unwrap())
     }
 
 }
@@ -166,7 +187,11 @@ impl<T> super::dag::SparseDirectedGraph<T> where T : super::dag::GraphNode {
         let mut leaves = Vec::new();
         while let Some((pointer, zorder)) = stack.pop() {
             if self.is_leaf(pointer) {
-                leaves.push(CellData::new(ExternalPointer::new(pointer, start.height - zorder.depth), zorder.to_cell()));
+                leaves.push(CellData::new(
+                    ExternalPointer::new(pointer, start.height - zorder.depth),
+                    zorder.to_cell(),
+                    self.leaf(pointer).unwrap()
+                ));
             } else { for i in 0 .. 4 {
                     let children = self.node(pointer).unwrap().children();
                     stack.push((children[i], zorder.step_down(i as u32)));
@@ -177,3 +202,30 @@ impl<T> super::dag::SparseDirectedGraph<T> where T : super::dag::GraphNode {
     }
 }
 
+
+#[test]
+fn aabbs_find_the_cells_they_cross() {
+    use crate::engine::world::World;
+    let mut world = World::default();
+    // A solid top left quadrant, and one unit cell at the top left of the bottom right quadrant
+    let solid = world.graph.leaf_pointer(Leaf(1)).unwrap();
+    let root = world.graph.get_root(Leaf(0), 2);
+    let root = world.graph.set_node(root, &[0], solid).unwrap();
+    // Held so the next edit can't happen in place
+    world.graph.add_tree_ref(root.pointer);
+    let root = world.graph.set_node(root, &[3, 0], solid).unwrap();
+    let cells = |aabb:Aabb| {
+        let mut cells:Vec<_> = ZorderPath::cells_intersecting_aabb(&world.graph, &world.blocks, root, aabb, Vec2::ONE).iter()
+            .map(|cell| (cell.cell, cell.pointer.height))
+            .collect();
+        cells.sort_by_key(|(cell, height)| (*height, cell.x, cell.y));
+        cells
+    };
+    // Across the middle, where the quadrants meet
+    assert_eq!(cells(Aabb::from_bounds(Vec2::splat(1.5), Vec2::splat(2.5))), [(UVec2::new(2, 2), 0), (UVec2::ZERO, 1)]);
+    // Inside the quadrant, which is one cell however small the box
+    assert_eq!(cells(Aabb::from_bounds(Vec2::splat(0.2), Vec2::splat(0.8))), [(UVec2::ZERO, 1)]);
+    // Off the grid, and over nothing but air
+    assert!(cells(Aabb::from_bounds(Vec2::splat(5.), Vec2::splat(6.))).is_empty());
+    assert!(cells(Aabb::from_bounds(Vec2::new(2.2, 0.2), Vec2::new(3.8, 1.8))).is_empty());
+}
@@ -1,77 +1,312 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use macroquad::input::*;
+use macroquad::math::Vec2;
+use crate::engine::world::World;
 
 pub type BindingId = usize;
+pub type Action<T> = Box<dyn FnMut(&mut T, &mut World)>;
+// Axis actions also get how far the axis moved
+pub type AxisAction<T> = Box<dyn FnMut(&mut T, &mut World, f32)>;
+
+// Most ticks allowed between two presses for them to count as a double click
+pub const DOUBLE_CLICK_TICKS: u64 = 15;
 
 #[allow(dead_code)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Deserialize)]
 pub enum InputTrigger {
     Pressed,
     Down,
     Released,
+    // A press soon after the last one
+    DoubleClick,
+    // Fires once the input has been down for this many ticks
+    Hold(u32),
+    // The only trigger axes have
+    Moved,
 }
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum InputType {
     Keyboard(KeyCode),
     Mouse(MouseButton),
+    Axis(Axis),
+}
+
+/// Mouse wheel scroll and mouse movement, in screen pixels for the mouse.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Deserialize)]
+pub enum Axis {
+    WheelX,
+    WheelY,
+    MouseX,
+    MouseY,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+}
+
+/// Set of modifier keys, either side of the keyboard counts.
+/// Written as a list of modifier names in config files.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize)]
+#[serde(from = "Vec<Modifier>")]
+pub struct Modifiers(u8);
+impl From<Vec<Modifier>> for Modifiers {
+    fn from(modifiers: Vec<Modifier>) -> Self {
+        modifiers.into_iter().fold(Self::NONE, |all, modifier| all.union(match modifier {
+            Modifier::Shift => Self::SHIFT,
+            Modifier::Control => Self::CONTROL,
+            Modifier::Alt => Self::ALT,
+        }))
+    }
+}
+#[allow(dead_code)]
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const SHIFT: Self = Self(1);
+    pub const CONTROL: Self = Self(2);
+    pub const ALT: Self = Self(4);
+
+    pub fn from_bits(bits: u8) -> Option<Self> { (bits < 8).then_some(Self(bits)) }
+    pub fn bits(self) -> u8 { self.0 }
+    pub fn union(self, other: Self) -> Self { Self(self.0 | other.0) }
+    pub fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+    pub fn count(self) -> u32 { self.0.count_ones() }
+
+    fn live() -> Self {
+        let mut modifiers = Self::NONE;
+        if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) { modifiers = modifiers.union(Self::SHIFT) }
+        if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) { modifiers = modifiers.union(Self::CONTROL) }
+        if is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt) { modifiers = modifiers.union(Self::ALT) }
+        modifiers
+    }
 }
 
-pub struct InputBinding<T> {
+/// An input along with the modifiers held when it happened.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InputEvent {
+    pub input: InputType,
+    pub trigger: InputTrigger,
+    pub modifiers: Modifiers,
+    // How far an axis moved, always 1 for keys and buttons
+    pub value: f32,
+}
+impl InputEvent {
+    pub fn new(input: InputType, trigger: InputTrigger, modifiers: Modifiers) -> Self {
+        Self { input, trigger, modifiers, value: 1. }
+    }
+    pub fn axis(axis: Axis, value: f32, modifiers: Modifiers) -> Self {
+        Self { input: InputType::Axis(axis), trigger: InputTrigger::Moved, modifiers, value }
+    }
+}
+
+/// How much input a context keeps from the contexts below it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize)]
+pub enum Blocking {
+    // Everything passes through
+    None,
+    // Inputs the context has a binding for stop here, along with the modifiers that binding needs
+    #[default]
+    Bound,
+    // Nothing gets past, for menus
+    All,
+}
+
+/// What a binding does when it fires.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BindingAction {
+    Named(String),
+    // Actions can't reach the handler, so the context stack is changed by bindings directly
+    PushContext(String),
+    PopContext,
+    // Removes the context if it's in the stack, otherwise pushes it
+    ToggleContext(String),
+}
+impl From<&str> for BindingAction {
+    fn from(name: &str) -> Self { Self::Named(name.to_string()) }
+}
+
+pub struct InputBinding {
+    context: String,
     input: InputType,
     trigger: InputTrigger,
-    action: Box<dyn FnMut(&mut T)>,
+    modifiers: Modifiers,
+    // Defaults to the number of modifiers, so chords win over the keys they're made of
+    priority: u32,
+    action: BindingAction,
     enabled: bool,
 }
+impl InputBinding {
+    // Held modifiers beyond the binding's own are fine, so shift doesn't stop you from moving
+    fn matches(&self, event: &InputEvent) -> bool {
+        self.engaged(event) && self.trigger == event.trigger
+    }
+    // Whether the event's input and modifiers make up this binding's chord, whatever the trigger
+    fn engaged(&self, event: &InputEvent) -> bool {
+        self.enabled && self.input == event.input && event.modifiers.contains(self.modifiers)
+    }
+}
+
+/// Everything that triggered a binding during one tick, and where the cursor was for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedTick {
+    pub events: Vec<InputEvent>,
+    pub cursor: Vec2,
+}
 
 pub struct InputHandler<T> {
-    bindings: HashMap<BindingId, InputBinding<T>>,
+    actions: HashMap<String, Action<T>>,
+    axis_actions: HashMap<String, AxisAction<T>>,
+    // Ordered so bindings always run in the order they were made, which keeps ticks deterministic
+    bindings: BTreeMap<BindingId, InputBinding>,
+    contexts: HashMap<String, Blocking>,
+    // Active contexts from the bottom up, input goes to the top one first
+    stack: Vec<String>,
     next_id: BindingId,
-    injected_events: Vec<(InputType, InputTrigger)>,
+    injected_events: Vec<InputEvent>,
+    // Presses and releases seen by poll which haven't been handled yet
+    polled_events: Vec<InputEvent>,
+    // Inputs held down as of the last poll, along with the modifiers
+    held: Vec<InputType>,
+    modifiers: Modifiers,
+    cursor: Vec2,
+    // Screen position of the mouse at the last poll, for the mouse axes
+    last_mouse: Option<Vec2>,
+    ticks_handled: u64,
+    // How many ticks in a row each input has been down for, for hold triggers
+    down_ticks: HashMap<InputType, u32>,
+    // The tick each input was last pressed on, for double clicks
+    last_press: HashMap<InputType, u64>,
+    recording: Option<Vec<RecordedTick>>,
+    // Ticks left to play back, live input is ignored until it runs out
+    replay: VecDeque<RecordedTick>,
 }
 #[allow(dead_code)]
 impl<T> InputHandler<T> where T: crate::DataAccess {
     pub fn new() -> Self {
         Self {
-            bindings: HashMap::new(),
+            actions: HashMap::new(),
+            axis_actions: HashMap::new(),
+            bindings: BTreeMap::new(),
+            contexts: HashMap::new(),
+            stack: Vec::new(),
             next_id: 0,
             injected_events: Vec::new(),
+            polled_events: Vec::new(),
+            held: Vec::new(),
+            modifiers: Modifiers::NONE,
+            cursor: Vec2::ZERO,
+            last_mouse: None,
+            ticks_handled: 0,
+            down_ticks: HashMap::new(),
+            last_press: HashMap::new(),
+            recording: None,
+            replay: VecDeque::new(),
         }
     }
 
-    pub fn bind_key<F>(&mut self, key: KeyCode, trigger: InputTrigger, action: F) -> BindingId 
+    /// Names an action so bindings can refer to it, replacing any action with the same name.
+    pub fn add_action<F>(&mut self, name: &str, action: F)
     where
-        F: FnMut(&mut T) + 'static,
+        F: FnMut(&mut T, &mut World) + 'static,
     {
-        self.bind(InputType::Keyboard(key), trigger, action)
+        self.actions.insert(name.to_string(), Box::new(action));
     }
 
-    pub fn bind_mouse<F>(&mut self, button: MouseButton, trigger: InputTrigger, action: F) -> BindingId 
+    pub fn has_action(&self, name: &str) -> bool { self.actions.contains_key(name) }
+
+    /// Names an action which axes can be bound to.
+    pub fn add_axis_action<F>(&mut self, name: &str, action: F)
     where
-        F: FnMut(&mut T) + 'static,
+        F: FnMut(&mut T, &mut World, f32) + 'static,
     {
-        self.bind(InputType::Mouse(button), trigger, action)
+        self.axis_actions.insert(name.to_string(), Box::new(action));
     }
 
-    fn bind<F>(&mut self, input: InputType, trigger: InputTrigger, action: F) -> BindingId 
-    where
-        F: FnMut(&mut T) + 'static,
-    {
+    pub fn has_axis_action(&self, name: &str) -> bool { self.axis_actions.contains_key(name) }
+
+    /// Defines a context, or changes how much an existing one blocks.
+    pub fn add_context(&mut self, name: &str, blocking: Blocking) {
+        self.contexts.insert(name.to_string(), blocking);
+    }
+
+    pub fn has_context(&self, name: &str) -> bool { self.contexts.contains_key(name) }
+
+    /// Returns success, contexts have to be added first and can only be in the stack once.
+    pub fn push_context(&mut self, name: &str) -> bool {
+        if !self.has_context(name) || self.stack.iter().any(|active| active == name) { return false }
+        self.stack.push(name.to_string());
+        true
+    }
+
+    pub fn pop_context(&mut self) -> Option<String> { self.stack.pop() }
+
+    /// Active contexts from the bottom up.
+    pub fn contexts(&self) -> &[String] { &self.stack }
+
+    /// Replaces the stack, skipping contexts which don't exist.
+    pub fn set_contexts(&mut self, stack: &[String]) {
+        self.stack.clear();
+        for name in stack { self.push_context(name); }
+    }
+
+    fn change_context(&mut self, change: &BindingAction) {
+        match change {
+            BindingAction::PushContext(name) => { self.push_context(name); }
+            BindingAction::PopContext => { self.pop_context(); }
+            BindingAction::ToggleContext(name) => match self.stack.iter().position(|active| active == name) {
+                Some(index) => { self.stack.remove(index); }
+                None => { self.push_context(name); }
+            }
+            BindingAction::Named(_) => {}
+        }
+    }
+
+    pub fn bind_key(&mut self, context: &str, key: KeyCode, trigger: InputTrigger, action: &str) -> BindingId {
+        self.bind(context, InputType::Keyboard(key), trigger, Modifiers::NONE, action.into())
+    }
+
+    pub fn bind_mouse(&mut self, context: &str, button: MouseButton, trigger: InputTrigger, action: &str) -> BindingId {
+        self.bind(context, InputType::Mouse(button), trigger, Modifiers::NONE, action.into())
+    }
+
+    /// Binding to an action or context which doesn't exist yet is fine, it does nothing until it's added.
+    pub fn bind(&mut self, context: &str, input: InputType, trigger: InputTrigger, modifiers: Modifiers, action: BindingAction) -> BindingId {
         let id = self.next_id;
         self.next_id += 1;
         
         self.bindings.insert(id, InputBinding {
+            context: context.to_string(),
             input,
             trigger,
-            action: Box::new(action),
+            modifiers,
+            priority: modifiers.count(),
+            action,
             enabled: true,
         });
         
         id
     }
 
+    /// Removes every binding and context, actions are kept.
+    pub fn clear_bindings(&mut self) {
+        self.bindings.clear();
+        self.contexts.clear();
+        self.stack.clear();
+        self.polled_events.clear();
+        self.held.clear();
+    }
+
+    /// Returns old value on success
+    pub fn set_priority(&mut self, id: BindingId, priority: u32) -> Option<u32> {
+        let binding = self.bindings.get_mut(&id)?;
+        Some(std::mem::replace(&mut binding.priority, priority))
+    }
+
     /// Injects an input which will be processed next frame
-    pub fn inject(&mut self, input: InputType, trigger: InputTrigger) {
-        self.injected_events.push((input, trigger));
+    pub fn inject(&mut self, event: InputEvent) {
+        self.injected_events.push(event);
     }
 
     /// Returns success
@@ -102,35 +337,248 @@ impl<T> InputHandler<T> where T: crate::DataAccess {
         Some(old_binding)
     }
 
-    fn should_trigger(input: InputType, trigger: InputTrigger, injected: &[(InputType, InputTrigger)]) -> bool {
-        let user = match input {
+    // Double clicks and holds are worked out from presses and downs, axes are sampled separately
+    fn is_live(input: InputType, trigger: InputTrigger) -> bool {
+        match input {
             InputType::Keyboard(key) => {
                 match trigger {
-                    InputTrigger::Pressed => is_key_pressed(key),
-                    InputTrigger::Down => is_key_down(key),
+                    InputTrigger::Pressed | InputTrigger::DoubleClick => is_key_pressed(key),
+                    InputTrigger::Down | InputTrigger::Hold(_) => is_key_down(key),
                     InputTrigger::Released => is_key_released(key),
+                    InputTrigger::Moved => false,
                 }
             },
             InputType::Mouse(button) => {
                 match trigger {
-                    InputTrigger::Pressed => is_mouse_button_pressed(button),
-                    InputTrigger::Down => is_mouse_button_down(button),
+                    InputTrigger::Pressed | InputTrigger::DoubleClick => is_mouse_button_pressed(button),
+                    InputTrigger::Down | InputTrigger::Hold(_) => is_mouse_button_down(button),
                     InputTrigger::Released => is_mouse_button_released(button),
+                    InputTrigger::Moved => false,
                 }
             },
-        };
-        if user || injected.contains(&(input, trigger)) { true }
-        else { false }
+            InputType::Axis(_) => false,
+        }
+    }
+
+    /// Samples the keyboard and mouse, called once per rendered frame. Without it the only input is injected.
+    /// Presses and releases wait for the next handle, so they fire once no matter how many ticks the frame runs.
+    pub fn poll(&mut self, cursor:Vec2) {
+        if self.is_replaying() { return }
+        self.cursor = cursor;
+        self.modifiers = Modifiers::live();
+        self.held.clear();
+        for binding in self.bindings.values() {
+            if !Self::is_live(binding.input, binding.trigger) { continue }
+            let trigger = match binding.trigger {
+                InputTrigger::Down | InputTrigger::Hold(_) => {
+                    if !self.held.contains(&binding.input) { self.held.push(binding.input) }
+                    continue
+                }
+                InputTrigger::DoubleClick => InputTrigger::Pressed,
+                trigger => trigger,
+            };
+            let event = InputEvent::new(binding.input, trigger, self.modifiers);
+            if !self.polled_events.contains(&event) { self.polled_events.push(event) }
+        }
+        let mouse = Vec2::from(mouse_position());
+        let moved = self.last_mouse.map_or(Vec2::ZERO, |last| mouse - last);
+        self.last_mouse = Some(mouse);
+        let (wheel_x, wheel_y) = mouse_wheel();
+        for (axis, value) in [(Axis::WheelX, wheel_x), (Axis::WheelY, wheel_y), (Axis::MouseX, moved.x), (Axis::MouseY, moved.y)] {
+            if value == 0. { continue }
+            // Movement adds up over frames which don't run a tick
+            match self.polled_events.iter_mut().find(|event| event.input == InputType::Axis(axis)) {
+                Some(event) => event.value += value,
+                None => self.polled_events.push(InputEvent::axis(axis, value, self.modifiers)),
+            }
+        }
     }
 
-    /// Loops through all bindings and executes actions
-    pub fn handle(&mut self, data: &mut T) where T: crate::DataAccess {
-        // This is gross but a good temporary solution
-        let injected = std::mem::take(&mut self.injected_events);
-        for binding in self.bindings.values_mut() {
-            if binding.enabled && Self::should_trigger(binding.input, binding.trigger, &injected) {
-                (binding.action)(data);
+    // Double clicks and holds depend on earlier ticks, so they're found here rather than in poll
+    fn derive_triggers(&mut self, events: &[InputEvent]) -> Vec<InputEvent> {
+        self.ticks_handled += 1;
+        let mut derived = Vec::new();
+        let mut down = Vec::new();
+        for event in events {
+            match event.trigger {
+                InputTrigger::Down if !down.contains(&event.input) => {
+                    down.push(event.input);
+                    let ticks = self.down_ticks.get(&event.input).copied().unwrap_or(0) + 1;
+                    self.down_ticks.insert(event.input, ticks);
+                    derived.push(InputEvent::new(event.input, InputTrigger::Hold(ticks), event.modifiers));
+                }
+                InputTrigger::Pressed => match self.last_press.insert(event.input, self.ticks_handled) {
+                    Some(last) if self.ticks_handled - last <= DOUBLE_CLICK_TICKS => {
+                        // A third press starts a new double click rather than finishing another
+                        self.last_press.remove(&event.input);
+                        derived.push(InputEvent::new(event.input, InputTrigger::DoubleClick, event.modifiers));
+                    }
+                    _ => {}
+                }
+                _ => {}
+            }
+        }
+        self.down_ticks.retain(|input, _| down.contains(input));
+        derived
+    }
+
+    /// Where the cursor was as of the current tick, live or replayed.
+    pub fn cursor(&self) -> Vec2 { self.cursor }
+
+    pub fn start_recording(&mut self) { self.recording = Some(Vec::new()) }
+
+    /// Returns every tick since recording started, or None if it wasn't.
+    pub fn stop_recording(&mut self) -> Option<Vec<RecordedTick>> { self.recording.take() }
+
+    /// Plays ticks back one per handle in place of live input.
+    pub fn replay(&mut self, ticks: Vec<RecordedTick>) {
+        self.replay = ticks.into();
+        self.polled_events.clear();
+        self.held.clear();
+    }
+
+    pub fn is_replaying(&self) -> bool { !self.replay.is_empty() }
+
+    /// Loops through all bindings and executes actions, called once per tick
+    pub fn handle(&mut self, data: &mut T, world: &mut World) where T: crate::DataAccess {
+        let events:Vec<_> = if let Some(recorded) = self.replay.pop_front() {
+            // Recordings already hold every double click and hold that fired
+            self.cursor = recorded.cursor;
+            recorded.events.into_iter().chain(self.injected_events.drain(..)).collect()
+        } else {
+            let mut events = std::mem::take(&mut self.polled_events);
+            events.extend(self.held.iter().map(|input| InputEvent::new(*input, InputTrigger::Down, self.modifiers)));
+            events.append(&mut self.injected_events);
+            let derived = self.derive_triggers(&events);
+            events.extend(derived);
+            events
+        };
+        let mut triggered = Vec::new();
+        // Applied once every binding has run, so a context change can't affect the tick it happens on
+        let mut context_changes = Vec::new();
+        let mut reaching = events;
+        for context in self.stack.iter().rev() {
+            let bindings:Vec<_> = self.bindings.values().filter(|binding| &binding.context == context).collect();
+            for binding in &bindings {
+                let Some(event) = reaching.iter().find(|event| binding.matches(event)) else { continue };
+                // A chord consumes its input, so while ctrl+s is held s doesn't also move you
+                let consumed = bindings.iter().any(|other| other.engaged(event) && other.priority > binding.priority);
+                if consumed { continue }
+                match (&binding.action, binding.input) {
+                    (BindingAction::Named(name), InputType::Axis(_)) => if let Some(action) = self.axis_actions.get_mut(name) { action(data, world, event.value) },
+                    (BindingAction::Named(name), _) => if let Some(action) = self.actions.get_mut(name) { action(data, world) },
+                    (change, _) => context_changes.push(change.clone()),
+                }
+                if !triggered.contains(event) { triggered.push(*event) }
             }
+            match self.contexts.get(context).copied().unwrap_or_default() {
+                Blocking::None => {}
+                Blocking::Bound => reaching.retain(|event| !bindings.iter().any(|binding| binding.engaged(event))),
+                Blocking::All => break,
+            }
+        }
+        for change in &context_changes { self.change_context(change) }
+        if let Some(recording) = &mut self.recording {
+            recording.push(RecordedTick { events: triggered, cursor: self.cursor });
         }
     }
 }
+
+// Every key which can be referred to by name, names match the KeyCode variants
+const NAMED_KEYS: [KeyCode; 70] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H, KeyCode::I,
+    KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R,
+    KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Escape, KeyCode::Tab, KeyCode::Backspace, KeyCode::Delete,
+    KeyCode::Equal, KeyCode::Minus, KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon,
+    KeyCode::LeftShift, KeyCode::RightShift, KeyCode::LeftControl, KeyCode::RightControl, KeyCode::LeftAlt, KeyCode::RightAlt,
+];
+
+/// Looks a key up by its KeyCode variant name, e.g. "W" or "Key1".
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    NAMED_KEYS.into_iter().find(|key| format!("{key:?}") == name)
+}
+
+const NAMED_BUTTONS: [MouseButton; 3] = [MouseButton::Left, MouseButton::Middle, MouseButton::Right];
+
+/// Looks a mouse button up by its MouseButton variant name, e.g. "Left".
+pub fn button_from_name(name: &str) -> Option<MouseButton> {
+    NAMED_BUTTONS.into_iter().find(|button| format!("{button:?}") == name)
+}
+const AXES: [Axis; 4] = [Axis::WheelX, Axis::WheelY, Axis::MouseX, Axis::MouseY];
+const TRIGGERS: [InputTrigger; 5] = [InputTrigger::Pressed, InputTrigger::Down, InputTrigger::Released, InputTrigger::DoubleClick, InputTrigger::Moved];
+
+// Numbers for inputs and triggers which stay the same between builds, used by recordings.
+// Keys take 0-255, buttons 256-511 and axes 512 up. Only named keys have one.
+
+pub fn input_code(input: InputType) -> Option<u64> {
+    let code = match input {
+        InputType::Keyboard(key) => NAMED_KEYS.iter().position(|named| *named == key)?,
+        InputType::Mouse(button) => 256 + NAMED_BUTTONS.iter().position(|named| *named == button)?,
+        InputType::Axis(axis) => 512 + AXES.iter().position(|named| *named == axis)?,
+    };
+    Some(code as u64)
+}
+
+pub fn input_from_code(code: u64) -> Option<InputType> {
+    let code = usize::try_from(code).ok()?;
+    match code {
+        0 .. 256 => NAMED_KEYS.get(code).map(|key| InputType::Keyboard(*key)),
+        256 .. 512 => NAMED_BUTTONS.get(code - 256).map(|button| InputType::Mouse(*button)),
+        _ => AXES.get(code - 512).map(|axis| InputType::Axis(*axis)),
+    }
+}
+
+// Holds come after every other trigger, offset by how long they are
+pub fn trigger_code(trigger: InputTrigger) -> u64 {
+    match trigger {
+        InputTrigger::Hold(ticks) => (TRIGGERS.len() as u64) + ticks as u64,
+        trigger => TRIGGERS.iter().position(|named| *named == trigger).unwrap() as u64,
+    }
+}
+
+pub fn trigger_from_code(code: u64) -> Option<InputTrigger> {
+    match TRIGGERS.get(usize::try_from(code).ok()?) {
+        Some(trigger) => Some(*trigger),
+        None => Some(InputTrigger::Hold(u32::try_from(code - TRIGGERS.len() as u64).ok()?)),
+    }
+}
+
+#[test]
+fn derived_triggers_and_chords() {
+    use crate::InputData;
+    let mut input = InputHandler::new();
+    input.add_action("move", |data: &mut InputData, _world: &mut World| data.edit_height += 1);
+    input.add_action("save", |data: &mut InputData, _world: &mut World| data.edit_height += 100);
+    input.add_action("double", |data: &mut InputData, _world: &mut World| data.edit_color.0 += 1);
+    input.add_action("hold", |data: &mut InputData, _world: &mut World| data.edit_color.0 += 10);
+    let s = InputType::Keyboard(KeyCode::S);
+    let left = InputType::Mouse(MouseButton::Left);
+    input.add_context("editor", Blocking::Bound);
+    input.push_context("editor");
+    input.bind("editor", s, InputTrigger::Down, Modifiers::NONE, "move".into());
+    input.bind("editor", s, InputTrigger::Pressed, Modifiers::CONTROL, "save".into());
+    input.bind("editor", left, InputTrigger::DoubleClick, Modifiers::NONE, "double".into());
+    input.bind("editor", left, InputTrigger::Hold(3), Modifiers::NONE, "hold".into());
+    let (mut data, mut world) = (InputData::default(), World::default());
+    // Holding ctrl+s saves without moving
+    input.inject(InputEvent::new(s, InputTrigger::Pressed, Modifiers::CONTROL));
+    input.inject(InputEvent::new(s, InputTrigger::Down, Modifiers::CONTROL));
+    input.handle(&mut data, &mut world);
+    input.inject(InputEvent::new(s, InputTrigger::Down, Modifiers::NONE));
+    input.handle(&mut data, &mut world);
+    assert_eq!(data.edit_height, 101);
+    // Two quick clicks, then holding the second for exactly three ticks
+    let (pressed, down) = (InputTrigger::Pressed, InputTrigger::Down);
+    let clicks = [vec![pressed, down], vec![], vec![pressed, down], vec![down], vec![down], vec![down]];
+    for (triggers, expected) in clicks.into_iter().zip([0, 0, 1, 1, 11, 11]) {
+        for trigger in triggers { input.inject(InputEvent::new(left, trigger, Modifiers::NONE)) }
+        input.handle(&mut data, &mut world);
+        assert_eq!(data.edit_color.0, expected);
+    }
+}
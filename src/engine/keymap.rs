@@ -0,0 +1,189 @@
+use serde::Deserialize;
+use super::input::{Axis, BindingAction, Blocking, InputHandler, InputTrigger, InputType, Modifiers, key_from_name, button_from_name};
+
+// Keymaps bind keys, mouse buttons and axes to the handler's named actions, grouped into contexts.
+// Keys and buttons are named after their KeyCode and MouseButton variants.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Keymap {
+    contexts: Vec<KeymapContext>,
+    // The contexts active to begin with, from the bottom up
+    stack: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapContext {
+    name: String,
+    #[serde(default)]
+    blocking: Blocking,
+    bindings: Vec<KeymapBinding>,
+}
+
+/// Exactly one of key, mouse or axis. The trigger defaults to Pressed, or Moved for axes.
+/// Exactly one of action, push_context, pop_context or toggle_context.
+/// Higher priorities consume their input, by default a binding's priority is its number of modifiers.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapBinding {
+    action: Option<String>,
+    push_context: Option<String>,
+    #[serde(default)]
+    pop_context: bool,
+    toggle_context: Option<String>,
+    key: Option<String>,
+    mouse: Option<String>,
+    axis: Option<Axis>,
+    trigger: Option<InputTrigger>,
+    #[serde(default)]
+    modifiers: Modifiers,
+    priority: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Json(serde_json::Error),
+    UnknownKey(String),
+    UnknownButton(String),
+    UnknownAction(String),
+    UnknownAxisAction(String),
+    UnknownContext(String),
+    // A binding needs one key, button or axis
+    MissingInput { context: String },
+    // And one thing to do
+    MissingAction { context: String },
+    // Axes can only be Moved and keys and buttons never are
+    WrongTrigger { context: String, trigger: InputTrigger },
+}
+impl std::fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json(error) => write!(f, "invalid json, {error}"),
+            Self::UnknownKey(key) => write!(f, "there's no key called {key}"),
+            Self::UnknownButton(button) => write!(f, "there's no mouse button called {button}"),
+            Self::UnknownAction(action) => write!(f, "there's no action called {action}"),
+            Self::UnknownAxisAction(action) => write!(f, "there's no axis action called {action}"),
+            Self::UnknownContext(context) => write!(f, "there's no context called {context}"),
+            Self::MissingInput { context } => write!(f, "a binding in {context} needs exactly one key, mouse button or axis"),
+            Self::MissingAction { context } => write!(f, "a binding in {context} needs exactly one action or context change"),
+            Self::WrongTrigger { context, trigger } => write!(f, "a binding in {context} can't use {trigger:?} with its input"),
+        }
+    }
+}
+impl std::error::Error for KeymapError {}
+impl From<serde_json::Error> for KeymapError {
+    fn from(error: serde_json::Error) -> Self { Self::Json(error) }
+}
+
+impl<T> InputHandler<T> where T: crate::DataAccess {
+    /// Replaces every context and binding with the keymap's, and the stack with its starting stack.
+    /// Within a context bindings run in the order they're listed.
+    /// If any of the keymap is invalid the current bindings are kept.
+    pub fn load_keymap(&mut self, json: &str) -> Result<(), KeymapError> {
+        let keymap: Keymap = serde_json::from_str(json)?;
+        let names:Vec<_> = keymap.contexts.iter().map(|context| context.name.clone()).collect();
+        let known = |name: &String| if names.contains(name) { Ok(()) } else { Err(KeymapError::UnknownContext(name.clone())) };
+        for name in &keymap.stack { known(name)? }
+        let mut bindings = Vec::new();
+        for context in &keymap.contexts {
+            let missing_action = || KeymapError::MissingAction { context: context.name.clone() };
+            for binding in &context.bindings {
+                let input = match (&binding.key, &binding.mouse, binding.axis) {
+                    (Some(key), None, None) => InputType::Keyboard(key_from_name(key).ok_or(KeymapError::UnknownKey(key.clone()))?),
+                    (None, Some(button), None) => InputType::Mouse(button_from_name(button).ok_or(KeymapError::UnknownButton(button.clone()))?),
+                    (None, None, Some(axis)) => InputType::Axis(axis),
+                    _ => return Err(KeymapError::MissingInput { context: context.name.clone() }),
+                };
+                let is_axis = matches!(input, InputType::Axis(_));
+                let trigger = binding.trigger.unwrap_or(if is_axis { InputTrigger::Moved } else { InputTrigger::Pressed });
+                if is_axis != (trigger == InputTrigger::Moved) { return Err(KeymapError::WrongTrigger { context: context.name.clone(), trigger }) }
+                let mut actions = Vec::new();
+                if let Some(name) = &binding.action {
+                    if is_axis && !self.has_axis_action(name) { return Err(KeymapError::UnknownAxisAction(name.clone())) }
+                    if !is_axis && !self.has_action(name) { return Err(KeymapError::UnknownAction(name.clone())) }
+                    actions.push(BindingAction::Named(name.clone()));
+                }
+                if let Some(name) = &binding.push_context {
+                    known(name)?;
+                    actions.push(BindingAction::PushContext(name.clone()));
+                }
+                if binding.pop_context { actions.push(BindingAction::PopContext) }
+                if let Some(name) = &binding.toggle_context {
+                    known(name)?;
+                    actions.push(BindingAction::ToggleContext(name.clone()));
+                }
+                let [action] = <[_; 1]>::try_from(actions).map_err(|_| missing_action())?;
+                bindings.push((&context.name, input, trigger, binding.modifiers, binding.priority, action));
+            }
+        }
+        self.clear_bindings();
+        for context in &keymap.contexts { self.add_context(&context.name, context.blocking) }
+        for (context, input, trigger, modifiers, priority, action) in bindings {
+            let id = self.bind(context, input, trigger, modifiers, action);
+            if let Some(priority) = priority { self.set_priority(id, priority); }
+        }
+        self.set_contexts(&keymap.stack);
+        Ok(())
+    }
+}
+
+#[test]
+fn most_specific_modifiers_win() {
+    use macroquad::input::KeyCode;
+    use crate::engine::world::World;
+    use crate::InputData;
+    use super::input::InputEvent;
+    let mut input = InputHandler::new();
+    input.add_action("nudge", |data: &mut InputData, _world: &mut World| data.edit_height += 1);
+    input.add_action("shove", |data: &mut InputData, _world: &mut World| data.edit_height += 10);
+    input.load_keymap(r#"{ "stack": ["editor"], "contexts": [{ "name": "editor", "bindings": [
+        { "action": "nudge", "key": "Z" },
+        { "action": "shove", "key": "Z", "modifiers": ["Control"] }
+    ] }] }"#).unwrap();
+    let (mut data, mut world) = (InputData::default(), World::default());
+    let z = InputType::Keyboard(KeyCode::Z);
+    input.inject(InputEvent::new(z, InputTrigger::Pressed, Modifiers::SHIFT));
+    input.handle(&mut data, &mut world);
+    input.inject(InputEvent::new(z, InputTrigger::Pressed, Modifiers::CONTROL));
+    input.handle(&mut data, &mut world);
+    assert_eq!(data.edit_height, 11);
+    // A broken keymap leaves the old one in place
+    let broken = r#"{ "stack": [], "contexts": [{ "name": "editor", "bindings": [{ "action": "fling", "key": "Z" }] }] }"#;
+    assert!(matches!(input.load_keymap(broken), Err(KeymapError::UnknownAction(_))));
+    input.inject(InputEvent::new(z, InputTrigger::Pressed, Modifiers::NONE));
+    input.handle(&mut data, &mut world);
+    assert_eq!(data.edit_height, 12);
+}
+
+#[test]
+fn contexts_block_lower_ones() {
+    use macroquad::input::KeyCode;
+    use crate::engine::world::World;
+    use crate::InputData;
+    use super::input::InputEvent;
+    let mut input = InputHandler::new();
+    input.add_action("walk", |data: &mut InputData, _world: &mut World| data.edit_height += 1);
+    input.add_action("paint", |data: &mut InputData, _world: &mut World| data.edit_height += 10);
+    input.load_keymap(r#"{ "stack": ["gameplay", "editor"], "contexts": [
+        { "name": "gameplay", "bindings": [
+            { "action": "walk", "key": "W", "trigger": "Down" },
+            { "toggle_context": "menu", "key": "Escape" }
+        ] },
+        { "name": "editor", "bindings": [{ "action": "paint", "key": "W", "trigger": "Down" }] },
+        { "name": "menu", "blocking": "All", "bindings": [{ "pop_context": true, "key": "Escape" }] }
+    ] }"#).unwrap();
+    let (mut data, mut world) = (InputData::default(), World::default());
+    let (w, escape) = (InputType::Keyboard(KeyCode::W), InputType::Keyboard(KeyCode::Escape));
+    // The editor's W hides gameplay's, escape isn't bound there so it opens the menu
+    for (input_type, trigger) in [(w, InputTrigger::Down), (escape, InputTrigger::Pressed)] {
+        input.inject(InputEvent::new(input_type, trigger, Modifiers::NONE));
+        input.handle(&mut data, &mut world);
+    }
+    assert_eq!((data.edit_height, input.contexts().len()), (10, 3));
+    // Nothing gets past the menu, which closes itself without gameplay opening it again
+    input.inject(InputEvent::new(w, InputTrigger::Down, Modifiers::NONE));
+    input.inject(InputEvent::new(escape, InputTrigger::Pressed, Modifiers::NONE));
+    input.handle(&mut data, &mut world);
+    assert_eq!((data.edit_height, input.contexts()), (10, &["gameplay".to_string(), "editor".to_string()][..]));
+}
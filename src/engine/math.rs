@@ -154,16 +154,6 @@ impl FloatUtils for Vec2 {
     fn zero_signum(self) -> Self::SignumType { IVec2::new(self.x.zero_signum(), self.y.zero_signum()) }
 }
 
-pub trait BVecUtils {
-    fn as_vec2(self) -> Vec2;
-}
-impl BVecUtils for BVec2 {
-    fn as_vec2(self) -> Vec2 { Vec2::new(
-        if self.x { 1. } else { 0. }, 
-        if self.y { 1. } else { 0. }) 
-    }
-}
-
 /// Converts angular velocity to tangential velocity for a point offset from the center of rotation.
 /// 
 /// # Arguments
@@ -4,4 +4,9 @@ pub mod physics;
 pub mod blocks;
 pub mod camera;
 pub mod input;
-pub mod math;
\ No newline at end of file
+pub mod math;
+pub mod binary;
+pub mod world;
+pub mod timestep;
+pub mod recording;
+pub mod keymap;
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use macroquad::math::Vec2;
+use crate::engine::entities::{Entity, EntityPool, ID};
+use crate::engine::math::Aabb;
+
+/// Pairs of entities which could touch each other, grouped so no hit in one island can move anything in another.
+/// Kinematic entities never pass an impulse on, so they show up in every island they touch without joining them together.
+#[derive(Debug, Clone, Default)]
+pub struct Island {
+    pub pairs: Vec<(ID, ID)>,
+}
+
+/// Everywhere the entity could reach within ticks, whichever way it spins.
+pub fn swept_bounds(entity:&Entity, ticks:f32) -> Option<Aabb> {
+    let aabb = entity.aabb().filter(|aabb| aabb.min().is_finite())?;
+    // Spinning sweeps the grid's furthest corner around the pivot, aabb is unrotated so the pivot is too
+    let pivot = entity.location.position + entity.mass.center_of_mass;
+    let (min, max) = (aabb.min(), aabb.max());
+    let reach = [min, max, Vec2::new(min.x, max.y), Vec2::new(max.x, min.y)].into_iter()
+        .map(|corner| corner.distance(pivot))
+        .fold(0., f32::max);
+    Some(Aabb::new(entity.pivot(), Vec2::splat(reach)).expand(entity.velocity * ticks))
+}
+
+/// Every pair whose bounds swept over the next ticks overlap, by sweep and prune along x.
+/// Sorted by slot, so pairs come out in the same order as the pool.
+pub fn overlapping_pairs(entities:&EntityPool, ticks:f32) -> Vec<(ID, ID)> {
+    pairs_within(entities, |entity| swept_bounds(entity, ticks))
+}
+
+/// Every pair whose bounds overlap, for whatever bounds are given. Entities without any are left out.
+pub fn pairs_within(entities:&EntityPool, bounds:impl Fn(&Entity) -> Option<Aabb>) -> Vec<(ID, ID)> {
+    let mut bounds:Vec<_> = entities.iter()
+        .filter_map(|entity| Some((entity, bounds(entity)?)))
+        .collect();
+    bounds.sort_by(|a, b| a.1.min().x.total_cmp(&b.1.min().x));
+    let mut pairs = Vec::new();
+    for (i, &(entity, aabb)) in bounds.iter().enumerate() {
+        for &(other, other_aabb) in &bounds[i + 1 ..] {
+            if other_aabb.min().x > aabb.max().x { break }
+            if !aabb.intersects(other_aabb).all() { continue }
+            let pair = if entity.id.index() < other.id.index() { (entity.id, other.id) } else { (other.id, entity.id) };
+            pairs.push(pair);
+        }
+    }
+    pairs.sort_by_key(|(a, b)| (a.index(), b.index()));
+    pairs
+}
+
+/// Every pair which could collide over the next ticks, split into islands.
+pub fn islands(entities:&EntityPool, ticks:f32) -> Vec<Island> {
+    let kinematic = |id:ID| entities.get_entity(id).unwrap().kinematic;
+    // Neither would give way, so they pass through each other
+    let pairs:Vec<_> = overlapping_pairs(entities, ticks).into_iter().filter(|&(a, b)| !(kinematic(a) && kinematic(b))).collect();
+    // Union find over the non kinematic entities
+    let mut parents:HashMap<ID, ID> = HashMap::new();
+    fn root(parents:&mut HashMap<ID, ID>, id:ID) -> ID {
+        let parent = *parents.entry(id).or_insert(id);
+        if parent == id { return id }
+        let root = root(parents, parent);
+        parents.insert(id, root);
+        root
+    }
+    for &(a, b) in &pairs {
+        if kinematic(a) || kinematic(b) { continue }
+        let (a, b) = (root(&mut parents, a), root(&mut parents, b));
+        if a != b { parents.insert(b, a); }
+    }
+    let mut islands:Vec<Island> = Vec::new();
+    let mut island_of:HashMap<ID, usize> = HashMap::new();
+    for (a, b) in pairs {
+        let key = root(&mut parents, if kinematic(a) { b } else { a });
+        let island = *island_of.entry(key).or_insert_with(|| { islands.push(Island::default()); islands.len() - 1 });
+        islands[island].pairs.push((a, b));
+    }
+    islands
+}
+
+#[test]
+fn kinematic_floors_dont_join_islands() {
+    use crate::engine::world::World;
+    use crate::engine::grid::dag::Leaf;
+    let mut world = World::default();
+    let spawn = |world:&mut World, x:f32, kinematic:bool| {
+        let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::new(x, 0.), 1);
+        let solid = world.graph.get_root(Leaf(1), 1);
+        entity.set_root(&mut world.graph, &world.blocks, solid);
+        entity.kinematic = kinematic;
+        world.entities.spawn(entity)
+    };
+    let floor = spawn(&mut world, 0., true);
+    let left = spawn(&mut world, -1.5, false);
+    let right = spawn(&mut world, 1.5, false);
+    let far = spawn(&mut world, 10., false);
+    let found = islands(&world.entities, 1.);
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].pairs, vec![(floor, left)]);
+    assert_eq!(found[1].pairs, vec![(floor, right)]);
+    // Moving fast enough reaches the far entity
+    world.entities.get_mut_entity(right).unwrap().velocity = Vec2::new(8., 0.);
+    let found = islands(&world.entities, 1.);
+    assert_eq!(found[1].pairs, vec![(floor, right), (right, far)]);
+}
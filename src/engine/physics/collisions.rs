@@ -1,19 +1,27 @@
 use std::cmp::{Reverse, Ordering};
 use std::collections::BinaryHeap;
 use macroquad::color::*;
-use crate::globals::*;
 use macroquad::math::{Vec2, BVec2, IVec2};
-use crate::engine::grid::{partition::*, dag::{Index, ExternalPointer}};
+use crate::engine::grid::{partition::*, dag::{Leaf, ExternalPointer}};
 use crate::engine::math::*;
-use crate::engine::entities::{Location, ID, Entity};
+use crate::engine::entities::{Location, ID, Entity, EntityPool};
+use crate::engine::blocks::BlockPalette;
+use crate::engine::world::{World, Graph};
 use std::f32::consts::PI;
+use super::broadphase::{self, Island};
+use super::{events, sensors};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
 
+// Everything is in the target's frame, turned about the target's pivot until the target's grid is axis aligned.
+// Both entities spin about their pivots, so that's what the motion is measured from.
 #[derive(Debug, Clone, derive_new::new)]
 pub struct CollisionObject {
     pub target_location : Location,
+    pub target_pivot : Vec2,
     pub target_angular : f32,
     pub target : ID,
-    pub owner_position : Vec2,
+    pub owner_pivot : Vec2,
     pub owner_angular : f32,
     pub owner : ID,
     pub linear_velocity : Vec2,
@@ -21,14 +29,14 @@ pub struct CollisionObject {
 }
 impl CollisionObject {
     pub fn projected_owner(&self, ticks_into_projection: f32) -> Vec2 {
-        (self.owner_position + self.linear_velocity*ticks_into_projection - self.target_location.position).rotate(Vec2::from_angle(self.target_angular * ticks_into_projection)) + self.target_location.position
+        (self.owner_pivot + self.linear_velocity*ticks_into_projection - self.target_pivot).rotate(Vec2::from_angle(self.target_angular * ticks_into_projection)) + self.target_pivot
     }
     pub fn instant_tangential_velocity(&self, offset: Vec2, ticks_into_projection: f32) -> Vec2 {
         self.linear_velocity
             + angular_to_tangential_velocity(self.owner_angular, offset)
             + angular_to_tangential_velocity(
                 -self.target_angular,
-                offset + self.projected_owner(ticks_into_projection) - self.target_location.position
+                offset + self.projected_owner(ticks_into_projection) - self.target_pivot
             )
     }
 }
@@ -141,109 +149,145 @@ impl CornerType {
     pub fn rotate(&self, rotation: f32) -> Self { Self::from_rotation(self.rotation() + rotation) }
 }
 
-#[derive(Debug)]
-struct Hit {
+#[derive(Debug, PartialEq)]
+pub struct Hit {
     pub owner : ID,
     pub target : ID,
     pub walls : BVec2,
     pub ticks : f32,
+    // Where the owner touched, relative to the target's pivot in its unrotated space
+    pub point : Vec2,
 }
 
-// Eventually turn this into an island generator
-fn collect_collision_objects() -> Vec<CollisionObject> {
+fn collect_collision_objects(entities:&EntityPool, pairs:&[(ID, ID)]) -> Vec<CollisionObject> {
     let mut objects = Vec::new();
-    let entities = ENTITIES.read();
-    for idx in 0..entities.entities.len() {
-        let owner = &entities.entities[idx];
-        for other_idx in idx + 1..entities.entities.len() {
-            let target = &entities.entities[other_idx];
-            if let Some(obj) = entity_to_collision_object(owner, target) { 
-                objects.push(obj); 
-            }
-            if let Some(obj) = entity_to_collision_object(target, owner) { 
-                objects.push(obj); 
-            }
+    for &(a, b) in pairs {
+        let (owner, target) = (entities.get_entity(a).unwrap(), entities.get_entity(b).unwrap());
+        if let Some(obj) = entity_to_collision_object(owner, target) { 
+            objects.push(obj); 
+        }
+        if let Some(obj) = entity_to_collision_object(target, owner) { 
+            objects.push(obj); 
         }
     }
     objects
 }
 
-fn apply_drag() {
-    const DRAG_MULTIPLIER: f32 = 0.95;
-    for entity in &mut ENTITIES.write().entities { 
-        entity.velocity = (entity.velocity * DRAG_MULTIPLIER).snap_zero();
-        entity.angular_velocity = (entity.angular_velocity * DRAG_MULTIPLIER).snap_zero();
+// Keeps whichever hits come first, islands finding hits at the same moment are all kept
+fn earliest(mut hits:Vec<Hit>, mut other:Vec<Hit>) -> Vec<Hit> {
+    let (Some(first), Some(other_first)) = (hits.first(), other.first()) else { hits.append(&mut other); return hits };
+    if first.ticks.approx_eq(other_first.ticks) { hits.append(&mut other); hits }
+    else if first.ticks.less(other_first.ticks) { hits } else { other }
+}
+
+fn apply_drag(entities:&mut EntityPool) {
+    for entity in entities.iter_mut() { 
+        entity.velocity = (entity.velocity * (1. - entity.body.linear_damping)).snap_zero();
+        entity.angular_velocity = (entity.angular_velocity * (1. - entity.body.angular_damping)).snap_zero();
     }
 }
 
-fn tick_entities(delta_tick: f32) {
-    for entity in &mut ENTITIES.write().entities {
+fn tick_entities(entities:&mut EntityPool, delta_tick: f32) {
+    for entity in entities.iter_mut() {
         entity.location.position += (entity.velocity * delta_tick).snap_zero();
         entity.rel_rotate((entity.angular_velocity * delta_tick).snap_zero());
     }
 }
 
-fn apply_normal_force(static_thing: ID, hit: Hit) {
-    let mut entities = ENTITIES.write();
-    let target = entities.get_entity(hit.target).unwrap();
-    let rel_velocity = entities.get_entity(hit.owner).unwrap().velocity - target.velocity;
-    let world_impulse = (rel_velocity.rotate(Vec2::from_angle(-target.rotation)) * hit.walls.as_vec2()).rotate(target.forward);
-    let objects = [(hit.owner, -1.0), (hit.target, 1.0)];
-    for (id, multiplier) in objects {
-        if id != static_thing {
-            let entity = entities.get_mut_entity(id).unwrap();
-            entity.velocity = (entity.velocity + world_impulse * multiplier).snap_zero();
-            entity.angular_velocity = 0.;
-        }
-    }
+pub fn just_move(world:&mut World) {
+    tick_entities(&mut world.entities, 1.);
+    apply_drag(&mut world.entities);
 }
 
-pub fn just_move() {
-    tick_entities(1.);
-    apply_drag();
-}
+// Zero tick hits in a row before the pairs still being hit are stopped and left be for the rest of the tick
+const WEDGE_LIMIT: usize = 8;
 
-pub fn n_body_collisions(static_thing: ID) {
+pub fn n_body_collisions(world:&mut World) {
+    let start = sensors::poses(&world.entities);
+    // Resting contacts are settled up front, so they don't turn into a hit every tick
+    world.contacts.refresh(&world.graph, &world.blocks, &world.entities);
+    world.contacts.warm_start(&mut world.entities);
+    world.contacts.solve(&mut world.entities);
     let mut tick_max = 1.;
-    let mut wedge_count = 0;
+    let (mut wedge_count, mut resting) = (0, Vec::new());
     loop {
-        let objects = collect_collision_objects();
-        let mut actions = find_next_action(objects, tick_max);
-        let Some(mut hit) = actions.pop() else {
-            tick_entities(tick_max); break
+        // Only pairs the broadphase can't rule out over the rest of the tick are marched
+        let mut islands = broadphase::islands(&world.entities, tick_max);
+        for island in &mut islands { island.pairs.retain(|pair| !resting.contains(pair)) }
+        let (actions, trail) = march_islands(world, islands, tick_max);
+        for point in trail { world.camera.draw_point(point, 0.02, RED) }
+        let Some(ticks) = actions.first().map(|hit| hit.ticks) else {
+            tick_entities(&mut world.entities, tick_max); break
         };
-        if hit.ticks.is_zero() {
+        if ticks.is_zero() {
+            // Hits the manifold can't hold, with nothing left closing or no solid wall behind them, are found again straight away.
+            // Every pair set resting is one fewer to march, so this always runs out
             wedge_count += 1;
-            // if wedge_count == 2: Balance the velocity of the axis we double tap
-            // ^ Only relevant when we have elastic collisions?
-            if wedge_count == 3 { hit.walls = BVec2::TRUE }
+            if wedge_count >= WEDGE_LIMIT {
+                wedge_count = 0;
+                for hit in &actions {
+                    for id in [hit.owner, hit.target] {
+                        let entity = world.entities.get_mut_entity(id).unwrap();
+                        if !entity.kinematic { entity.stop() }
+                    }
+                    let pair = if hit.owner.index() < hit.target.index() { (hit.owner, hit.target) } else { (hit.target, hit.owner) };
+                    if !resting.contains(&pair) { resting.push(pair) }
+                }
+            }
         } else {
             wedge_count = 0;
-            tick_max -= hit.ticks;
-            tick_entities(hit.ticks);
+            tick_max -= ticks;
+            tick_entities(&mut world.entities, ticks);
         }
-        apply_normal_force(static_thing, hit);
+        // Every hit at this moment is a point of its pair's manifold, a flat face landing flat touches at both corners
+        for hit in &actions { world.contacts.add_hit(&world.entities, hit) }
+        world.contacts.refresh(&world.graph, &world.blocks, &world.entities);
+        world.contacts.solve(&mut world.entities);
     }
-    apply_drag();
+    apply_drag(&mut world.entities);
+    world.contacts.refresh(&world.graph, &world.blocks, &world.entities);
+    events::dispatch(world, &start);
+}
+
+// Islands are marched on the thread pool and reduced in order, so ties between them break the same way every run.
+// Returns the earliest hits along with every point marched through, which are drawn afterwards from the main thread.
+fn march_islands(world:&World, islands:Vec<Island>, tick_max:f32) -> (Vec<Hit>, Vec<Vec2>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    return islands.into_par_iter().map(|island| march_island(world, island, tick_max)).reduce(|| (Vec::new(), Vec::new()), combine_marches);
+    #[cfg(target_arch = "wasm32")]
+    islands.into_iter().map(|island| march_island(world, island, tick_max)).fold((Vec::new(), Vec::new()), combine_marches)
+}
+
+fn march_island(world:&World, island:Island, tick_max:f32) -> (Vec<Hit>, Vec<Vec2>) {
+    let mut trail = Vec::new();
+    let objects = collect_collision_objects(&world.entities, &island.pairs);
+    (find_next_action(world, objects, tick_max, &mut trail), trail)
+}
+
+fn combine_marches((hits, mut trail):(Vec<Hit>, Vec<Vec2>), (other_hits, mut other_trail):(Vec<Hit>, Vec<Vec2>)) -> (Vec<Hit>, Vec<Vec2>) {
+    trail.append(&mut other_trail);
+    (earliest(hits, other_hits), trail)
 }
 
 use super::raymarching::{Motion, Line};
-fn find_next_action(objects:Vec<CollisionObject>, tick_max:f32) -> Vec<Hit> {
+fn find_next_action(world:&World, objects:Vec<CollisionObject>, tick_max:f32, trail:&mut Vec<Vec2>) -> Vec<Hit> {
     let mut ticks_to_action = tick_max;
     let mut action:Vec<Hit> = Vec::new();
     'objectloop : for mut object in objects {
         while let Some(Reverse(mut cur_corner)) = object.particles.pop() {
             if cur_corner.ticks_into_projection.greater(ticks_to_action) { continue 'objectloop }
             let motion = Motion::new(
-                object.target_location.position,
+                object.target_pivot,
                 object.projected_owner(cur_corner.ticks_into_projection),
                 cur_corner.offset,
                 object.linear_velocity,
                 object.target_angular,
                 object.owner_angular,
             );
+            trail.push(motion.project_to(0.));
             // Why aren't we just passing object?
             let Some(ticks_to_hit) = next_intersection(
+                world,
                 motion,
                 object.instant_tangential_velocity(cur_corner.offset, cur_corner.ticks_into_projection),
                 object.target_location,
@@ -255,17 +299,22 @@ fn find_next_action(objects:Vec<CollisionObject>, tick_max:f32) -> Vec<Hit> {
             cur_corner.corner_type = cur_corner.corner_type.rotate(ticks_to_hit*(object.owner_angular-object.target_angular));
             let velocity = object.instant_tangential_velocity(cur_corner.offset, cur_corner.ticks_into_projection);
             if let Some(walls_hit) = hitting_wall(
-                gate::point_to_real_cells(object.target_location, motion.project_to(ticks_to_hit)),
+                &world.blocks,
+                gate::point_to_real_cells(&world.graph, object.target_location, motion.project_to(ticks_to_hit)),
                 velocity,
                 cur_corner.corner_type
             ) {
-                if cur_corner.ticks_into_projection.less(ticks_to_action) { action.clear() }
-                action.push( Hit {
+                let hit = Hit {
                     owner : object.owner,
                     target : object.target,
                     walls : walls_hit,
-                    ticks : cur_corner.ticks_into_projection
-                } );
+                    ticks : cur_corner.ticks_into_projection,
+                    point : motion.project_to(ticks_to_hit) - object.target_pivot,
+                };
+                // Already resting there, so it's the solver's to hold apart rather than a hit to stop at
+                if hit.ticks.is_zero() && world.contacts.holds(&hit) { continue }
+                if cur_corner.ticks_into_projection.less(ticks_to_action) { action.clear() }
+                action.push(hit);
                 ticks_to_action = cur_corner.ticks_into_projection;
             } else { object.particles.push(Reverse(cur_corner)) }
         }
@@ -274,6 +323,7 @@ fn find_next_action(objects:Vec<CollisionObject>, tick_max:f32) -> Vec<Hit> {
 }
 
 fn next_intersection(
+    world: &World,
     motion: Motion,
     itvel: Vec2,
     hitting_location: Location,
@@ -281,12 +331,11 @@ fn next_intersection(
     mut tick_max: f32,
 ) -> Option<f32> {
     let point = motion.project_to(0.);
-    CAMERA.read().draw_point(point, 0.02, RED);
     let hitting_aabb = hitting_location.to_aabb();
     let within_bounds = hitting_aabb.contains(point);
 
-    let cells = gate::point_to_real_cells(hitting_location, point);
-    if hitting_wall(cells, itvel, corner_type).is_some() { return Some(0.) }
+    let cells = gate::point_to_real_cells(&world.graph, hitting_location, point);
+    if hitting_wall(&world.blocks, cells, itvel, corner_type).is_some() { return Some(0.) }
     let index = 2 * (itvel.y.greater(0.) as usize) | (itvel.x.greater(0.) as usize);
     let grid_top_left = hitting_aabb.min();
     let (top_left, bottom_right) = if let Some(cell) = cells[index] {
@@ -323,24 +372,27 @@ pub fn entity_to_collision_object(owner:&Entity, target:&Entity) -> Option<Colli
     let align_target = Vec2::from_angle(-target.rotation);
     let rel_velocity = (owner.velocity - target.velocity).rotate(align_target).snap_zero();
     if rel_velocity.is_zero() && (owner.angular_velocity - target.angular_velocity).is_zero() { return None }
-    let rotated_owner_pos = (owner.location.position - target.location.position).rotate(align_target) + target.location.position;
+    let target_pivot = target.pivot();
+    let rotated_owner_pivot = (owner.pivot() - target_pivot).rotate(align_target) + target_pivot;
     for corners in owner.corners.iter() {
         for i in 0..4 {
             // Cull any corner which isn't exposed
             if corners.mask & (1 << i) == 0 { continue }
-            let offset = ((corners.points[i] - offset).rotate(owner.forward) + owner.location.position - target.location.position)
-                .rotate(align_target) + target.location.position - rotated_owner_pos;
+            let offset = (corners.points[i] - offset - owner.mass.center_of_mass).rotate(owner.forward).rotate(align_target);
             collision_points.push(Reverse(Particle::new(
                 offset,
                 CornerType::from_index(i).rotate(owner.rotation - target.rotation)
             )));
         }
     }
+    // The target's grid center, once turned about its pivot to line up with the axes
+    let aligned_location = Location { position: target_pivot - target.mass.center_of_mass, ..target.location };
     Some(CollisionObject::new(
-        target.location,
+        aligned_location,
+        target_pivot,
         target.angular_velocity,
         target.id,
-        rotated_owner_pos,
+        rotated_owner_pivot,
         owner.angular_velocity,
         owner.id,
         rel_velocity,
@@ -351,14 +403,14 @@ pub fn entity_to_collision_object(owner:&Entity, target:&Entity) -> Option<Colli
 #[derive(Debug, Clone, derive_new::new)]
 pub struct Corners {
     pub points : [Vec2; 4],
-    pub index : Index,
+    pub leaf : Leaf,
     pub mask : u8,
 }
 
 pub mod corner_handling {
     use super::*;
 
-    fn cell_corner_mask(start: ExternalPointer, zorder: ZorderPath) -> u8 {
+    fn cell_corner_mask(graph:&Graph, blocks:&BlockPalette, start: ExternalPointer, zorder: ZorderPath) -> u8 {
         const CORNER_CHECKS: [([(IVec2, u8); 3], u8); 4] = [
             // Format: ([(offset, step_direction), ...], corner_mask_bit)
             ([(IVec2::new(-1, 0), 0b01), (IVec2::new(0, -1), 0b10), (IVec2::new(-1, -1), 0b11)], 0b0001), // Top Left
@@ -374,8 +426,8 @@ pub mod corner_handling {
                 for _ in 0 .. start.height - check_zorder.depth {
                     check_zorder = check_zorder.step_down(direction as u32)
                 }
-                let pointer = GRAPH.read().read(start, &check_zorder.steps()).unwrap();
-                if BLOCKS.is_solid_index(*pointer.pointer) { continue 'corner }
+                let pointer = graph.read(start, &check_zorder.steps()).unwrap();
+                if blocks.is_solid_leaf(graph.leaf(pointer.pointer).unwrap()) { continue 'corner }
             }
             exposed_mask |= mask;
         }
@@ -394,15 +446,15 @@ pub mod corner_handling {
         ]
     }
 
-    pub fn tree_corners(start:ExternalPointer, min_cell_length:Vec2) -> Vec<Corners> {
-        let leaves = GRAPH.read().dfs_leaf_cells(start);
+    pub fn tree_corners(graph:&Graph, blocks:&BlockPalette, start:ExternalPointer, min_cell_length:Vec2) -> Vec<Corners> {
+        let leaves = graph.dfs_leaf_cells(start);
         let mut corners = Vec::new();
         for cell in leaves {
             let zorder = ZorderPath::from_cell(cell.cell, start.height - cell.pointer.height);
             corners.push( Corners::new(
                 cell_corners(cell, min_cell_length),
-                cell.pointer.pointer,
-                if !BLOCKS.is_solid_index(*cell.pointer.pointer) { 0 } else { cell_corner_mask(start, zorder) }
+                cell.leaf,
+                if !blocks.is_solid_leaf(cell.leaf) { 0 } else { cell_corner_mask(graph, blocks, start, zorder) }
             ));
         }
         corners 
@@ -411,13 +463,15 @@ pub mod corner_handling {
     
 }
 
-fn hitting_wall(position_data:[Option<CellData>; 4], velocity:Vec2, corner_type:CornerType) -> Option<BVec2> {
+fn hitting_wall(blocks:&BlockPalette, position_data:[Option<CellData>; 4], velocity:Vec2, corner_type:CornerType) -> Option<BVec2> {
+    // Spin can leave a corner momentarily still against the target, it isn't moving into anything
+    if velocity.is_zero() { return None }
     let mut hit_walls = corner_type.hittable_walls(velocity);
     // Velocity Check
     {
         let hit = match corner_type.checks(velocity) {
-            CheckZorders::One(idx) => BLOCKS.is_solid_cell(position_data[idx]),
-            CheckZorders::Two([idx1, idx2]) => BLOCKS.is_solid_cell(position_data[idx1]) | BLOCKS.is_solid_cell(position_data[idx2]),
+            CheckZorders::One(idx) => blocks.is_solid_cell(position_data[idx]),
+            CheckZorders::Two([idx1, idx2]) => blocks.is_solid_cell(position_data[idx1]) | blocks.is_solid_cell(position_data[idx2]),
         };
         if !velocity.x.is_zero() { hit_walls.x &= hit }
         if !velocity.y.is_zero() { hit_walls.y &= hit }
@@ -432,11 +486,77 @@ fn hitting_wall(position_data:[Option<CellData>; 4], velocity:Vec2, corner_type:
             _ => unreachable!(),
         };
         let slide = BVec2::new(
-            BLOCKS.is_solid_cell(position_data[idxs[0]]),
-            BLOCKS.is_solid_cell(position_data[idxs[1]])
+            blocks.is_solid_cell(position_data[idxs[0]]),
+            blocks.is_solid_cell(position_data[idxs[1]])
         );
 
         if slide != BVec2::FALSE { hit_walls &= slide }
     };
     (hit_walls != BVec2::FALSE).then_some(hit_walls)
 }
+
+#[test]
+fn parallel_marching_matches_serial() {
+    use crate::engine::camera::NoOpSink;
+    let mut world = World::default();
+    world.camera.set_sink(Box::new(NoOpSink));
+    let spawn = |world:&mut World, position:Vec2, block:Leaf, velocity:Vec2| {
+        let mut entity = Entity::blank(&mut world.graph, &world.blocks, position, 0);
+        let root = world.graph.get_root(block, 0);
+        entity.set_root(&mut world.graph, &world.blocks, root);
+        entity.velocity = velocity;
+        // The walls don't move, so only the balls join islands
+        entity.kinematic = velocity == Vec2::ZERO;
+        world.entities.spawn(entity)
+    };
+    // Rows far enough apart to be islands of their own, two of them hitting at the same time so the tie has to break the same way
+    for (row, gap) in [0.4, 0.2, 0.6, 0.2, 0.3].into_iter().enumerate() {
+        let y = row as f32 * 10.;
+        spawn(&mut world, Vec2::new(1. + gap, y), Leaf(3), Vec2::ZERO);
+        spawn(&mut world, Vec2::new(0., y), Leaf(1), Vec2::new(0.5, 0.));
+    }
+    let islands = broadphase::islands(&world.entities, 1.);
+    assert_eq!(islands.len(), 5);
+    let serial = islands.iter().cloned().map(|island| march_island(&world, island, 1.)).fold((Vec::new(), Vec::new()), combine_marches);
+    for _ in 0 .. 8 {
+        let (hits, trail) = march_islands(&world, islands.clone(), 1.);
+        assert_eq!(hits, serial.0);
+        assert_eq!(trail, serial.1);
+    }
+    // Both tied rows, each wall and ball hitting the other, in island order
+    let mut rows:Vec<_> = serial.0.iter().map(|hit| hit.owner.index() / 2).collect();
+    rows.dedup();
+    assert_eq!(rows, [1, 3]);
+}
+
+#[test]
+fn stacks_come_to_rest() {
+    use crate::engine::camera::NoOpSink;
+    let mut world = World::default();
+    world.camera.set_sink(Box::new(NoOpSink));
+    let spawn = |world:&mut World, position:Vec2, height:u32, kinematic:bool| {
+        let mut entity = Entity::blank(&mut world.graph, &world.blocks, position, height);
+        let root = world.graph.get_root(Leaf(if kinematic { 3 } else { 1 }), height);
+        entity.set_root(&mut world.graph, &world.blocks, root);
+        entity.kinematic = kinematic;
+        world.entities.spawn(entity)
+    };
+    // A floor with a wall at its right end, a box stacked on another and a row shoved into the corner
+    spawn(&mut world, Vec2::new(0., 5.), 3, true);
+    spawn(&mut world, Vec2::new(6., -1.), 2, true);
+    let stack = [Vec2::new(-3., 0.5), Vec2::new(-3., -0.55)].map(|position| spawn(&mut world, position, 0, false));
+    let row = [0.5, 1.7, 2.9].map(|x| spawn(&mut world, Vec2::new(x, 0.4 - x / 10.), 0, false));
+    for tick in 0 .. 200 {
+        for &id in stack.iter().chain(&row) { world.entities.get_mut_entity(id).unwrap().velocity.y += 0.02 }
+        if tick < 100 { world.entities.get_mut_entity(row[0]).unwrap().velocity.x += 0.02 }
+        n_body_collisions(&mut world);
+    }
+    // Settled flat and still, touching but not sunk into whatever they're resting on
+    let rests = [(stack[0], Vec2::new(-3., 0.5)), (stack[1], Vec2::new(-3., -0.5)), (row[0], Vec2::new(1.5, 0.5)), (row[1], Vec2::new(2.5, 0.5)), (row[2], Vec2::new(3.5, 0.5))];
+    for (id, rest) in rests {
+        let entity = world.entities.get_entity(id).unwrap();
+        assert!(entity.location.position.distance(rest) < 1e-2);
+        assert!(entity.rotation.sin().abs() < 1e-2);
+        assert!(entity.velocity.length() < 1e-2 && entity.angular_velocity.abs() < 1e-2);
+    }
+}
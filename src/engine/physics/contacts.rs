@@ -0,0 +1,293 @@
+use macroquad::math::Vec2;
+use crate::engine::grid::partition::{gate, CellData};
+use crate::engine::entities::{Entity, EntityPool, Location, ID};
+use crate::engine::blocks::BlockPalette;
+use crate::engine::math::*;
+use crate::engine::world::Graph;
+use crate::engine::binary::{ByteReader, ByteWriter};
+use super::collisions::Hit;
+
+// Passes over every contact each solve, more settles stacks further at the cost of time
+const SOLVER_ITERATIONS: usize = 16;
+// How far apart a contact's sides can drift before it's dropped
+const CONTACT_DISTANCE: f32 = 1e-3;
+// Penetration left alone, and the fraction of the rest pushed out each tick
+const SLOP: f32 = 1e-4;
+const POSITION_CORRECTION: f32 = 0.2;
+
+/// One point a pair touches at, kept between ticks so the solver can start from the impulses it needed last time.
+#[derive(Debug, Clone, Copy)]
+pub struct ContactPoint {
+    // Where each side touches, relative to its pivot in its own unrotated space
+    owner_anchor: Vec2,
+    target_anchor: Vec2,
+    // Out of the target's wall towards the owner, in the target's unrotated space
+    local_normal: Vec2,
+    pub point: Vec2,
+    pub normal: Vec2,
+    pub depth: f32,
+    // The target's cell behind the wall, found again every refresh
+    pub cell: Option<CellData>,
+    // Accumulated over every solve this tick, and applied up front next tick
+    pub normal_impulse: f32,
+    pub tangent_impulse: f32,
+    // Separating speed restitution asks for, only for the tick the hit happened in
+    bounce: f32,
+}
+
+/// Everywhere one entity's corners rest against another entity's walls.
+/// A pair touching both ways round has a manifold for each.
+#[derive(Debug, Clone)]
+pub struct Manifold {
+    pub owner: ID,
+    pub target: ID,
+    pub points: Vec<ContactPoint>,
+}
+
+// Everything the impulse needs from one side of a contact
+struct Side {
+    arm: Vec2,
+    inverse_mass: f32,
+    inverse_inertia: f32,
+}
+impl Side {
+    fn new(entity:&Entity, point:Vec2) -> Self {
+        let (inverse_mass, inverse_inertia) = if entity.kinematic { (0., 0.) } else {
+            (entity.mass.inverse_mass(), entity.mass.inverse_inertia())
+        };
+        Self { arm: point - entity.pivot(), inverse_mass, inverse_inertia }
+    }
+    // How much an impulse along direction changes this side's velocity at the contact along direction
+    fn response(&self, direction:Vec2) -> f32 {
+        self.inverse_mass + self.arm.perp_dot(direction).powi(2) * self.inverse_inertia
+    }
+    fn velocity(&self, entity:&Entity) -> Vec2 {
+        entity.velocity + angular_to_tangential_velocity(entity.angular_velocity, self.arm)
+    }
+    fn apply(&self, entity:&mut Entity, impulse:Vec2) {
+        if entity.kinematic { return }
+        entity.velocity = (entity.velocity + impulse * self.inverse_mass).snap_zero();
+        entity.angular_velocity = (entity.angular_velocity + self.arm.perp_dot(impulse) * self.inverse_inertia).snap_zero();
+    }
+}
+
+fn to_local(entity:&Entity, point:Vec2) -> Vec2 { (point - entity.pivot()).rotate(entity.forward * Vec2::new(1., -1.)) }
+fn to_world(entity:&Entity, local:Vec2) -> Vec2 { entity.pivot() + local.rotate(entity.forward) }
+
+// The two quadrants around a point which are behind a wall facing normal
+fn behind(normal:Vec2) -> [usize; 2] {
+    if normal.x > 0.5 { [0, 2] } else if normal.x < -0.5 { [1, 3] } else if normal.y > 0.5 { [0, 1] } else { [2, 3] }
+}
+
+/// Every resting contact, solved with sequential impulses before anything is marched.
+#[derive(Debug, Clone, Default)]
+pub struct Contacts {
+    manifolds: Vec<Manifold>,
+}
+impl Contacts {
+    pub fn manifolds(&self) -> &[Manifold] { &self.manifolds }
+    pub fn clear(&mut self) { self.manifolds.clear() }
+
+    /// Entities are written as their place in order, so they can be found again once they're loaded under new handles.
+    /// Cells aren't written, the next refresh finds them again.
+    pub fn write(&self, writer:&mut ByteWriter, order:&[ID]) {
+        let place = |id| order.iter().position(|other| *other == id).unwrap() as u64;
+        writer.varint(self.manifolds.len() as u64);
+        for manifold in &self.manifolds {
+            writer.varint(place(manifold.owner));
+            writer.varint(place(manifold.target));
+            writer.varint(manifold.points.len() as u64);
+            for contact in &manifold.points {
+                for vector in [contact.owner_anchor, contact.target_anchor, contact.local_normal, contact.point, contact.normal] { writer.vec2(vector) }
+                for scalar in [contact.depth, contact.normal_impulse, contact.tangent_impulse, contact.bounce] { writer.f32(scalar) }
+            }
+        }
+    }
+
+    pub fn read(reader:&mut ByteReader, order:&[ID]) -> Option<Self> {
+        let entity = |reader:&mut ByteReader| order.get(usize::try_from(reader.varint()?).ok()?).copied();
+        let mut manifolds = Vec::new();
+        for _ in 0 .. reader.varint()? {
+            let (owner, target) = (entity(reader)?, entity(reader)?);
+            let mut points = Vec::new();
+            for _ in 0 .. reader.varint()? {
+                points.push(ContactPoint {
+                    owner_anchor: reader.vec2()?,
+                    target_anchor: reader.vec2()?,
+                    local_normal: reader.vec2()?,
+                    point: reader.vec2()?,
+                    normal: reader.vec2()?,
+                    depth: reader.f32()?,
+                    cell: None,
+                    normal_impulse: reader.f32()?,
+                    tangent_impulse: reader.f32()?,
+                    bounce: reader.f32()?,
+                });
+            }
+            manifolds.push(Manifold { owner, target, points });
+        }
+        Some(Self { manifolds })
+    }
+
+    /// Moves every point along with its entities, dropping the ones which came apart or slid off the end of their wall.
+    pub fn refresh(&mut self, graph:&Graph, blocks:&BlockPalette, entities:&EntityPool) {
+        self.manifolds.retain_mut(|manifold| {
+            let (Some(owner), Some(target)) = (entities.get_entity(manifold.owner), entities.get_entity(manifold.target)) else { return false };
+            let location = Location { position: target.pivot() - target.mass.center_of_mass, ..target.location };
+            manifold.points.retain_mut(|contact| {
+                let corner = to_local(target, to_world(owner, contact.owner_anchor));
+                let separation = (corner - contact.target_anchor).dot(contact.local_normal);
+                if separation > CONTACT_DISTANCE { return false }
+                // The wall stays where it was, the point on it follows the corner
+                contact.target_anchor = corner - contact.local_normal * separation;
+                let cells = gate::point_to_real_cells(graph, location, target.pivot() + contact.target_anchor);
+                contact.cell = behind(contact.local_normal).into_iter().map(|quadrant| cells[quadrant]).find(|&cell| blocks.is_solid_cell(cell)).flatten();
+                if contact.cell.is_none() { return false }
+                contact.normal = contact.local_normal.rotate(target.forward);
+                contact.point = to_world(target, contact.target_anchor + contact.local_normal * separation / 2.);
+                contact.depth = (-separation).max(0.);
+                true
+            });
+            !manifold.points.is_empty()
+        });
+    }
+
+    /// Whether every wall the hit closed in on is already a contact point where it happened.
+    pub fn holds(&self, hit:&Hit) -> bool {
+        let Some(manifold) = self.manifolds.iter().find(|manifold| manifold.owner == hit.owner && manifold.target == hit.target) else { return false };
+        let held = |axis:Vec2| manifold.points.iter().any(|contact| {
+            contact.local_normal.dot(axis) != 0. && (contact.target_anchor - hit.point).length() < CONTACT_DISTANCE
+        });
+        (!hit.walls.x || held(Vec2::X)) && (!hit.walls.y || held(Vec2::Y))
+    }
+
+    /// Adds a contact for every wall a hit closed in on, keeping the impulses of any point already there.
+    pub fn add_hit(&mut self, entities:&EntityPool, hit:&Hit) {
+        let owner = entities.get_entity(hit.owner).unwrap();
+        let target = entities.get_entity(hit.target).unwrap();
+        let point = to_world(target, hit.point);
+        let (owner_side, target_side) = (Side::new(owner, point), Side::new(target, point));
+        let rel_velocity = (owner_side.velocity(owner) - target_side.velocity(target)).rotate(target.forward * Vec2::new(1., -1.));
+        let restitution = owner.body.restitution.max(target.body.restitution);
+        let owner_anchor = to_local(owner, point);
+        let index = match self.manifolds.iter().position(|manifold| manifold.owner == hit.owner && manifold.target == hit.target) {
+            Some(index) => index,
+            None => { self.manifolds.push(Manifold { owner: hit.owner, target: hit.target, points: Vec::new() }); self.manifolds.len() - 1 }
+        };
+        let manifold = &mut self.manifolds[index];
+        for (hit_wall, axis) in [(hit.walls.x, Vec2::X), (hit.walls.y, Vec2::Y)] {
+            // Pointing back out of the wall the owner was moving into
+            let local_normal = -axis * rel_velocity.dot(axis).signum();
+            let closing = -rel_velocity.dot(local_normal);
+            // Not snapped to zero, the hit was found with a slightly different velocity and even a sliver has to go
+            if !hit_wall || closing <= 0. { continue }
+            let contact = ContactPoint {
+                owner_anchor,
+                target_anchor: hit.point,
+                local_normal,
+                point,
+                normal: local_normal.rotate(target.forward),
+                depth: 0.,
+                cell: None,
+                normal_impulse: 0.,
+                tangent_impulse: 0.,
+                bounce: restitution * closing,
+            };
+            let existing = manifold.points.iter_mut()
+                .find(|other| other.local_normal == local_normal && (other.owner_anchor - owner_anchor).length() < CONTACT_DISTANCE);
+            match existing {
+                Some(other) => *other = ContactPoint { normal_impulse: other.normal_impulse, tangent_impulse: other.tangent_impulse, bounce: other.bounce.max(contact.bounce), ..contact },
+                None => manifold.points.push(contact),
+            }
+        }
+    }
+
+    /// Applies last tick's impulses again at the start of a tick, resting contacts need about the same every tick.
+    pub fn warm_start(&mut self, entities:&mut EntityPool) {
+        for manifold in &mut self.manifolds {
+            for contact in &mut manifold.points {
+                let impulse = contact.normal * contact.normal_impulse + contact.normal.perp() * contact.tangent_impulse;
+                apply_pair(entities, manifold.owner, manifold.target, contact.point, impulse);
+                // Bounces only happen in the tick of their hit
+                contact.bounce = 0.;
+            }
+        }
+    }
+
+    /// Sequential impulses, each contact in turn pushes its pair apart just enough to stop them closing,
+    /// with friction limited by the push. Impulses accumulate so a later pass can take back what an earlier one overdid.
+    pub fn solve(&mut self, entities:&mut EntityPool) {
+        for _ in 0 .. SOLVER_ITERATIONS {
+            for manifold in &mut self.manifolds {
+                let owner = entities.get_entity(manifold.owner).unwrap();
+                let target = entities.get_entity(manifold.target).unwrap();
+                let friction = (owner.body.friction * target.body.friction).sqrt();
+                for contact in &mut manifold.points {
+                    let (owner, target) = (entities.get_entity(manifold.owner).unwrap(), entities.get_entity(manifold.target).unwrap());
+                    let (owner_side, target_side) = (Side::new(owner, contact.point), Side::new(target, contact.point));
+                    let rel_velocity = owner_side.velocity(owner) - target_side.velocity(target);
+                    let normal = contact.normal;
+                    let effective = owner_side.response(normal) + target_side.response(normal);
+                    if effective.is_zero() { continue }
+                    let wanted = contact.bounce.max(POSITION_CORRECTION * (contact.depth - SLOP).max(0.));
+                    let total = (contact.normal_impulse + (wanted - rel_velocity.dot(normal)) / effective).max(0.);
+                    let change = total - contact.normal_impulse;
+                    contact.normal_impulse = total;
+                    apply_sides(entities, manifold.owner, manifold.target, &owner_side, &target_side, normal * change);
+
+                    let (owner, target) = (entities.get_entity(manifold.owner).unwrap(), entities.get_entity(manifold.target).unwrap());
+                    let rel_velocity = owner_side.velocity(owner) - target_side.velocity(target);
+                    let tangent = normal.perp();
+                    let effective = owner_side.response(tangent) + target_side.response(tangent);
+                    if effective.is_zero() { continue }
+                    let limit = friction * contact.normal_impulse;
+                    let total = (contact.tangent_impulse - rel_velocity.dot(tangent) / effective).clamp(-limit, limit);
+                    let change = total - contact.tangent_impulse;
+                    contact.tangent_impulse = total;
+                    apply_sides(entities, manifold.owner, manifold.target, &owner_side, &target_side, tangent * change);
+                }
+            }
+        }
+    }
+}
+
+// The owner's pushed along impulse and the target the other way
+fn apply_sides(entities:&mut EntityPool, owner:ID, target:ID, owner_side:&Side, target_side:&Side, impulse:Vec2) {
+    owner_side.apply(entities.get_mut_entity(owner).unwrap(), impulse);
+    target_side.apply(entities.get_mut_entity(target).unwrap(), -impulse);
+}
+fn apply_pair(entities:&mut EntityPool, owner:ID, target:ID, point:Vec2, impulse:Vec2) {
+    let owner_side = Side::new(entities.get_entity(owner).unwrap(), point);
+    let target_side = Side::new(entities.get_entity(target).unwrap(), point);
+    apply_sides(entities, owner, target, &owner_side, &target_side, impulse);
+}
+
+#[test]
+fn pushing_into_a_wall_rests_on_a_warm_contact() {
+    use crate::engine::world::World;
+    use crate::engine::camera::NoOpSink;
+    use crate::engine::grid::dag::Leaf;
+    use super::collisions::n_body_collisions;
+    let mut world = World::default();
+    world.camera.set_sink(Box::new(NoOpSink));
+    let mut spawn = |x:f32, kinematic:bool| {
+        let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::new(x, 0.), 0);
+        let solid = world.graph.get_root(Leaf(1), 0);
+        entity.set_root(&mut world.graph, &world.blocks, solid);
+        entity.kinematic = kinematic;
+        world.entities.spawn(entity)
+    };
+    let (wall, pusher) = (spawn(2., true), spawn(0., false));
+    for _ in 0 .. 20 {
+        world.entities.get_mut_entity(pusher).unwrap().velocity.x += 0.2;
+        n_body_collisions(&mut world);
+    }
+    let entity = world.entities.get_entity(pusher).unwrap();
+    assert!((entity.location.position.x - 1.).abs() < 1e-4);
+    assert!(entity.angular_velocity.abs() < 1e-4);
+    // Both corners of the face touch, and last tick's push is carried over to the next
+    let manifold = &world.contacts.manifolds()[0];
+    assert!([(manifold.owner, manifold.target), (manifold.target, manifold.owner)].contains(&(pusher, wall)));
+    assert_eq!(manifold.points.len(), 2);
+    assert!(manifold.points.iter().all(|contact| contact.normal_impulse > 0.));
+}
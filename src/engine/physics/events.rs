@@ -0,0 +1,240 @@
+use macroquad::math::Vec2;
+use crate::engine::grid::dag::{Index, Leaf};
+use crate::engine::entities::ID;
+use crate::engine::world::World;
+use crate::engine::grid::partition::CellData;
+use super::contacts::{Contacts, Manifold};
+use super::sensors::{self, Overlap, Pose, Visit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactPhase {
+    // First tick the pair touched
+    Begin,
+    // Still touching since last tick
+    Persist,
+    // Came apart since last tick, the rest of the event is from when they last touched
+    End,
+}
+
+/// One pair of entities touching, reported once per tick while they do.
+/// The owner's corners are what touch the target's walls, a pair touching both ways round reports each.
+#[derive(Debug, Clone, Copy)]
+pub struct CollisionEvent {
+    pub phase: ContactPhase,
+    pub owner: ID,
+    pub target: ID,
+    // The contact pushing hardest, in world space
+    pub point: Vec2,
+    // Out of the target's wall towards the owner
+    pub normal: Vec2,
+    // Everything the target pushed on the owner with this tick, zero once they've ended
+    pub impulse: Vec2,
+    // The target's cell behind the contact and what block fills it
+    pub cell: Index,
+    pub block: Leaf,
+}
+impl CollisionEvent {
+    fn from_manifold(manifold:&Manifold) -> Option<Self> {
+        let strongest = manifold.points.iter().max_by(|a, b| a.normal_impulse.total_cmp(&b.normal_impulse))?;
+        let cell = strongest.cell?;
+        Some(Self {
+            phase: ContactPhase::Begin,
+            owner: manifold.owner,
+            target: manifold.target,
+            point: strongest.point,
+            normal: strongest.normal,
+            impulse: manifold.points.iter()
+                .map(|contact| contact.normal * contact.normal_impulse + contact.normal.perp() * contact.tangent_impulse)
+                .sum(),
+            cell: cell.pointer.pointer,
+            block: cell.leaf,
+        })
+    }
+    fn same_pair(&self, other:&Self) -> bool { self.owner == other.owner && self.target == other.target }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPhase {
+    Enter,
+    // The rest of the event is from when the corner was last inside
+    Exit,
+}
+
+/// A corner of the owner entering or leaving one of the target's sensor cells.
+#[derive(Debug, Clone, Copy)]
+pub struct OverlapEvent {
+    pub phase: OverlapPhase,
+    pub owner: ID,
+    pub target: ID,
+    pub point: Vec2,
+    pub cell: CellData,
+}
+
+// What listeners are picked out by
+trait Event : Copy {
+    fn involves(&self, id:ID) -> bool;
+    fn block(&self) -> Leaf;
+}
+impl Event for CollisionEvent {
+    fn involves(&self, id:ID) -> bool { self.owner == id || self.target == id }
+    fn block(&self) -> Leaf { self.block }
+}
+impl Event for OverlapEvent {
+    fn involves(&self, id:ID) -> bool { self.owner == id || self.target == id }
+    fn block(&self) -> Leaf { self.cell.leaf }
+}
+
+type Listener<E> = Box<dyn FnMut(&mut World, &E) + Send + Sync>;
+
+struct Listeners<E> {
+    entity: Vec<(ID, Listener<E>)>,
+    block: Vec<(Leaf, Listener<E>)>,
+}
+impl<E> Default for Listeners<E> {
+    fn default() -> Self { Self { entity: Vec::new(), block: Vec::new() } }
+}
+
+/// This tick's collision and sensor events, and whoever wants to hear about them.
+#[derive(Default)]
+pub struct CollisionEvents {
+    events: Vec<CollisionEvent>,
+    overlap_events: Vec<OverlapEvent>,
+    // Last tick's touching pairs and occupied sensors, to tell what's new and find what's gone
+    touching: Vec<CollisionEvent>,
+    overlapping: Vec<Overlap>,
+    listeners: Listeners<CollisionEvent>,
+    overlap_listeners: Listeners<OverlapEvent>,
+}
+impl CollisionEvents {
+    /// Every event from the last tick, in the order the contacts were made.
+    pub fn events(&self) -> &[CollisionEvent] { &self.events }
+    /// Every corner which entered or left a sensor last tick.
+    pub fn overlap_events(&self) -> &[OverlapEvent] { &self.overlap_events }
+
+    /// Called with every event the entity is either side of.
+    pub fn on_entity<F>(&mut self, id:ID, listener:F)
+    where
+        F: FnMut(&mut World, &CollisionEvent) + Send + Sync + 'static,
+    {
+        self.listeners.entity.push((id, Box::new(listener)));
+    }
+
+    /// Called with every event where the wall touched is made of block.
+    pub fn on_block<F>(&mut self, block:Leaf, listener:F)
+    where
+        F: FnMut(&mut World, &CollisionEvent) + Send + Sync + 'static,
+    {
+        self.listeners.block.push((block, Box::new(listener)));
+    }
+
+    /// Called whenever the entity's corners enter or leave a sensor, or another's corners enter or leave one of its sensors.
+    pub fn on_entity_overlap<F>(&mut self, id:ID, listener:F)
+    where
+        F: FnMut(&mut World, &OverlapEvent) + Send + Sync + 'static,
+    {
+        self.overlap_listeners.entity.push((id, Box::new(listener)));
+    }
+
+    /// Called whenever a corner enters or leaves a sensor made of block.
+    pub fn on_block_overlap<F>(&mut self, block:Leaf, listener:F)
+    where
+        F: FnMut(&mut World, &OverlapEvent) + Send + Sync + 'static,
+    {
+        self.overlap_listeners.block.push((block, Box::new(listener)));
+    }
+
+    /// Forgets which pairs were touching, for when every entity is replaced.
+    pub fn reset(&mut self) {
+        self.events.clear();
+        self.overlap_events.clear();
+        self.touching.clear();
+        self.overlapping.clear();
+    }
+
+    // Compares the contacts at the end of a tick with the ones at the end of the last
+    fn record(&mut self, contacts:&Contacts) {
+        let touching:Vec<_> = contacts.manifolds().iter().filter_map(CollisionEvent::from_manifold).collect();
+        self.events.clear();
+        for event in &touching {
+            let phase = if self.touching.iter().any(|last| last.same_pair(event)) { ContactPhase::Persist } else { ContactPhase::Begin };
+            self.events.push(CollisionEvent { phase, ..*event });
+        }
+        for last in &self.touching {
+            if touching.iter().any(|event| event.same_pair(last)) { continue }
+            self.events.push(CollisionEvent { phase: ContactPhase::End, impulse: Vec2::ZERO, ..*last });
+        }
+        self.touching = touching;
+    }
+
+    fn record_overlaps(&mut self, visits:Vec<Visit>) {
+        let event = |phase, overlap:&Overlap| OverlapEvent { phase, owner: overlap.owner, target: overlap.target, point: overlap.point, cell: overlap.cell };
+        self.overlap_events.clear();
+        for visit in &visits {
+            if !self.overlapping.iter().any(|last| last.same_cell(&visit.first)) { self.overlap_events.push(event(OverlapPhase::Enter, &visit.first)) }
+            // Left again within the tick, maybe having only passed through
+            if !visit.inside { self.overlap_events.push(event(OverlapPhase::Exit, &visit.last)) }
+        }
+        for last in &self.overlapping {
+            if visits.iter().any(|visit| visit.first.same_cell(last)) { continue }
+            self.overlap_events.push(event(OverlapPhase::Exit, last));
+        }
+        self.overlapping = visits.into_iter().filter(|visit| visit.inside).map(|visit| visit.last).collect();
+    }
+}
+
+/// Records this tick's events and hands them to their listeners.
+/// Sensors are checked along the way from where every entity was at the start of the tick.
+/// Listeners are taken out of the world while they run, so any they add from inside are kept but miss this tick.
+pub fn dispatch(world:&mut World, start:&[Pose]) {
+    world.collisions.record(&world.contacts);
+    let visits = sensors::visits(world, start);
+    world.collisions.record_overlaps(visits);
+    let events = world.collisions.events.clone();
+    notify(world, |collisions| &mut collisions.listeners, &events);
+    let events = world.collisions.overlap_events.clone();
+    notify(world, |collisions| &mut collisions.overlap_listeners, &events);
+}
+
+fn notify<E: Event>(world:&mut World, listeners:fn(&mut CollisionEvents) -> &mut Listeners<E>, events:&[E]) {
+    let mut running = std::mem::take(listeners(&mut world.collisions));
+    for event in events {
+        for (id, listener) in &mut running.entity {
+            if event.involves(*id) { listener(world, event) }
+        }
+        for (block, listener) in &mut running.block {
+            if *block == event.block() { listener(world, event) }
+        }
+    }
+    let added = listeners(&mut world.collisions);
+    running.entity.append(&mut added.entity);
+    running.block.append(&mut added.block);
+    *added = running;
+}
+
+#[test]
+fn contacts_begin_persist_and_end() {
+    use std::sync::{Arc, Mutex};
+    use super::{collisions::n_body_collisions, spawn_filled, test_world};
+    let mut world = test_world();
+    // The wall's taller than the ball, so only the ball's corners touch
+    let (wall, ball) = (spawn_filled(&mut world, 2.5, Leaf(3), 1), spawn_filled(&mut world, 0., Leaf(1), 0));
+    world.entities.get_mut_entity(wall).unwrap().kinematic = true;
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let log = heard.clone();
+    world.collisions.on_entity(ball, move |_, event| log.lock().unwrap().push(event.phase));
+    let walls_hit = Arc::new(Mutex::new(0));
+    let count = walls_hit.clone();
+    world.collisions.on_block(Leaf(3), move |_, _| *count.lock().unwrap() += 1);
+
+    world.entities.get_mut_entity(ball).unwrap().velocity.x = 1.5;
+    n_body_collisions(&mut world);
+    let event = world.collisions.events()[0];
+    assert_eq!((event.phase, event.block), (ContactPhase::Begin, Leaf(3)));
+    assert!(event.impulse.x < 0. && event.normal.x < 0.);
+    world.entities.get_mut_entity(ball).unwrap().velocity.x = 0.5;
+    n_body_collisions(&mut world);
+    world.entities.get_mut_entity(ball).unwrap().velocity.x = -0.5;
+    n_body_collisions(&mut world);
+    assert_eq!(*heard.lock().unwrap(), [ContactPhase::Begin, ContactPhase::Persist, ContactPhase::End]);
+    assert_eq!(*walls_hit.lock().unwrap(), 3);
+}
@@ -1,2 +1,28 @@
+pub mod broadphase;
 pub mod collisions;
-pub mod raymarching;
\ No newline at end of file
+pub mod contacts;
+pub mod events;
+pub mod raymarching;
+pub mod rigid_body;
+pub mod sensors;
+
+// Shared by the physics tests
+#[cfg(test)]
+use crate::engine::{world::World, entities::{Entity, ID}, grid::dag::Leaf};
+
+/// A world whose camera doesn't draw anything.
+#[cfg(test)]
+pub fn test_world() -> World {
+    let mut world = World::default();
+    world.camera.set_sink(Box::new(crate::engine::camera::NoOpSink));
+    world
+}
+
+/// Spawns an entity filled with one block, centered on the x axis.
+#[cfg(test)]
+pub fn spawn_filled(world:&mut World, x:f32, block:Leaf, height:u32) -> ID {
+    let mut entity = Entity::blank(&mut world.graph, &world.blocks, macroquad::math::Vec2::new(x, 0.), height);
+    let root = world.graph.get_root(block, height);
+    entity.set_root(&mut world.graph, &world.blocks, root);
+    world.entities.spawn(entity)
+}
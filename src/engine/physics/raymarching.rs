@@ -6,39 +6,13 @@ mod intersection {
     use macroquad::math::Vec2;
     use crate::engine::math::*;
 
-    struct IterationTracker {
-        convergency: SimpleConvergency<f32>,
-        iterations: usize,
-    }
-    impl roots::Convergency<f32> for IterationTracker {
-        fn is_root_found(&mut self, y: f32) -> bool {
-            self.convergency.is_root_found(y)
-        }
-
-        fn is_converged(&mut self, x1: f32, x2: f32) -> bool {
-            self.convergency.is_converged(x1, x2)
-        }
-
-        fn is_iteration_limit_reached(&mut self, iter: usize) -> bool {
-            self.iterations = iter;
-            self.convergency.is_iteration_limit_reached(iter)
-        }
-    }
-    impl Default for IterationTracker {
-        fn default() -> Self {
-            Self {
-                convergency: SimpleConvergency { eps: FP_EPSILON, max_iter: 20 },
-                iterations: 0,
-            }
-        }
-    }
-
     #[derive(Debug, Clone, Copy)]
     pub enum Line {
         Vertical(f32),
         Horizontal(f32),
     }
 
+    // Centers are the pivots each entity spins about, not the middle of their grids
     #[derive(Debug, Clone, Copy, new)]
     pub struct Motion {
         pub target_center: Vec2,
@@ -65,12 +39,10 @@ mod intersection {
                 Line::Horizontal(y) => (y, 1),
             };
             let f = |t: f32| target - self.project_to(t)[x_or_y];
-            let mut iter_tracker = IterationTracker::default();
-            match find_root_brent(0., max_time, &f, &mut iter_tracker) {
-                Ok(t) => {
-                    println!("Found in {} iterations", iter_tracker.iterations);
-                    Some(t)
-                },
+            // Spinning entities can take Brent close to 30 iterations to pin down within FP_EPSILON, 20 wasn't always enough
+            let mut convergency = SimpleConvergency { eps: FP_EPSILON, max_iter: 64 };
+            match find_root_brent(0., max_time, &f, &mut convergency) {
+                Ok(t) => Some(t),
                 Err(SearchError::NoConvergency) => panic!("Increase iterations"),
                 Err(_) => None
             }
@@ -0,0 +1,201 @@
+use macroquad::math::Vec2;
+use serde::{Serialize, Deserialize};
+use crate::engine::grid::dag::{ExternalPointer, Leaf};
+use crate::engine::grid::fold::NodeFold;
+use crate::engine::grid::partition::{cell_length, center_to_edge};
+use crate::engine::blocks::BlockPalette;
+use crate::engine::world::Graph;
+
+/// How an entity responds to collisions, set per entity and saved with it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Body {
+    // 0 stops dead along the wall, 1 bounces back just as fast. A pair bounces as much as its bounciest
+    pub restitution: f32,
+    // Coulomb friction coefficient, a pair uses the geometric mean of theirs
+    pub friction: f32,
+    // Fraction of velocity lost every tick
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+}
+impl Default for Body {
+    fn default() -> Self {
+        Self {
+            restitution: 0.,
+            friction: 0.3,
+            linear_damping: 0.05,
+            angular_damping: 0.05,
+        }
+    }
+}
+
+/// Derived from the grid's solid cells, each weighing its block's density times its area.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MassProperties {
+    pub mass: f32,
+    // Offset from the entity's position, in its unrotated space
+    pub center_of_mass: Vec2,
+    // Moment of inertia about the center of mass
+    pub inertia: f32,
+}
+impl MassProperties {
+    pub fn from_tree(graph:&mut Graph, blocks:&BlockPalette, start:ExternalPointer, min_cell_length:Vec2) -> Self {
+        let unit = graph.fold(&MassFold::new(blocks), start.pointer);
+        if unit.mass <= 0. { return Self::default() }
+        let side = cell_length(start.height, min_cell_length);
+        let area = side.x * side.y;
+        let mass = unit.mass * area;
+        let center = unit.first / unit.mass * side;
+        let second = unit.second * side * side * area;
+        Self {
+            mass,
+            center_of_mass: center - center_to_edge(start.height, min_cell_length),
+            // Moved from the top left corner to the center of mass by the parallel axis theorem
+            inertia: second.x + second.y - mass * center.length_squared(),
+        }
+    }
+
+    /// Massless grids have nothing to collide with, they're treated as immovable rather than dividing by zero.
+    pub fn inverse_mass(&self) -> f32 {
+        if self.mass > 0. { 1. / self.mass } else { 0. }
+    }
+
+    pub fn inverse_inertia(&self) -> f32 {
+        if self.inertia > 0. { 1. / self.inertia } else { 0. }
+    }
+}
+
+// A node's mass and its first and second moments about its top left corner, over a unit square.
+// Nodes are drawn at whatever size their height says, so these are scaled up afterwards.
+#[derive(Debug, Clone, Copy, Default)]
+struct UnitMoments {
+    mass: f32,
+    first: Vec2,
+    second: Vec2,
+}
+
+// Densities are copied out so the fold doesn't borrow the palette, blocks are never changed once added
+struct MassFold(Vec<f32>);
+impl MassFold {
+    fn new(blocks:&BlockPalette) -> Self {
+        Self((0 .. blocks.leaf_count() as u16).map(|leaf| {
+            if blocks.is_solid_leaf(Leaf(leaf)) { blocks.density(Leaf(leaf)) } else { 0. }
+        }).collect())
+    }
+}
+impl NodeFold for MassFold {
+    type Value = UnitMoments;
+    fn leaf(&self, leaf:Leaf) -> UnitMoments {
+        let density = self.0.get(leaf.0 as usize).copied().unwrap_or(0.);
+        UnitMoments { mass: density, first: Vec2::splat(density / 2.), second: Vec2::splat(density / 3.) }
+    }
+    fn branch(&self, children:[UnitMoments; 4]) -> UnitMoments {
+        let mut moments = UnitMoments::default();
+        for (zorder, child) in children.iter().enumerate() {
+            // Each child covers a quarter of the square, x = offset + child_x / 2
+            let offset = Vec2::new((zorder & 1) as f32, (zorder >> 1) as f32) / 2.;
+            moments.mass += child.mass / 4.;
+            moments.first += (offset * child.mass + child.first / 2.) / 4.;
+            moments.second += (offset * offset * child.mass + offset * child.first + child.second / 4.) / 4.;
+        }
+        moments
+    }
+}
+
+#[test]
+fn lopsided_grids_weigh_their_solid_half() {
+    let mut graph = Graph::new(4);
+    let blocks = BlockPalette::default();
+    let solid = graph.leaf_pointer(Leaf(1)).unwrap();
+    let root = graph.get_root(Leaf(0), 1);
+    // Solid top left and bottom left quarters of a 2x2 grid
+    let root = graph.set_node(root, &[0], solid).unwrap();
+    let root = graph.set_node(root, &[2], solid).unwrap();
+    let properties = MassProperties::from_tree(&mut graph, &blocks, root, Vec2::ONE);
+    assert_eq!(properties.mass, 2.);
+    assert_eq!(properties.center_of_mass, Vec2::new(-0.5, 0.));
+    // A 1x2 rectangle is (1 + 4) / 12 of its mass
+    assert!((properties.inertia - 2. * 5. / 12.).abs() < 1e-5);
+}
+
+// A block of one leaf at position, still unless given a velocity and kinematic if it isn't
+#[cfg(test)]
+fn spawn_body(world:&mut crate::engine::world::World, position:Vec2, height:u32, body:Body, velocity:Vec2) -> crate::engine::entities::ID {
+    use crate::engine::entities::Entity;
+    let mut entity = Entity::blank(&mut world.graph, &world.blocks, position, height);
+    let root = world.graph.get_root(Leaf(if velocity == Vec2::ZERO { 3 } else { 1 }), height);
+    entity.set_root(&mut world.graph, &world.blocks, root);
+    entity.velocity = velocity;
+    entity.kinematic = velocity == Vec2::ZERO;
+    entity.body = body;
+    world.entities.spawn(entity)
+}
+
+#[cfg(test)]
+use super::test_world;
+
+#[test]
+fn restitution_sets_the_bounce() {
+    use super::collisions::n_body_collisions;
+    let undamped = Body { linear_damping: 0., angular_damping: 0., ..Body::default() };
+    let bounce = |restitution:f32| {
+        let mut world = test_world();
+        spawn_body(&mut world, Vec2::new(1.5, 0.), 0, undamped, Vec2::ZERO);
+        let ball = spawn_body(&mut world, Vec2::ZERO, 0, Body { restitution, ..undamped }, Vec2::new(0.8, 0.));
+        n_body_collisions(&mut world);
+        world.entities.get_entity(ball).unwrap().velocity.x
+    };
+    assert!(bounce(0.).abs() < 1e-4);
+    assert!((bounce(0.5) + 0.4).abs() < 1e-3);
+    assert!((bounce(1.) + 0.8).abs() < 1e-3);
+}
+
+#[test]
+fn friction_slows_sliding() {
+    use super::collisions::n_body_collisions;
+    let slide = |friction:f32| {
+        let mut world = test_world();
+        let body = Body { friction, linear_damping: 0., angular_damping: 0., ..Body::default() };
+        spawn_body(&mut world, Vec2::new(0., 2.), 1, body, Vec2::ZERO);
+        // Landing on the floor while moving along it
+        let ball = spawn_body(&mut world, Vec2::new(0., 0.), 0, body, Vec2::new(0.05, 0.8));
+        n_body_collisions(&mut world);
+        world.entities.get_entity(ball).unwrap().velocity
+    };
+    let (slick, rough) = (slide(0.), slide(1.));
+    assert!(slick.y.abs() < 1e-4 && rough.y.abs() < 1e-4);
+    assert!((slick.x - 0.05).abs() < 1e-4);
+    // Friction can take at most as much as the landing pushed back, which is plenty to stop it
+    assert!(rough.x.abs() < 1e-4);
+}
+
+#[test]
+fn off_center_hits_spin() {
+    use super::collisions::n_body_collisions;
+    let mut world = test_world();
+    let body = Body { linear_damping: 0., angular_damping: 0., ..Body::default() };
+    // Only the top of the block reaches the wall
+    spawn_body(&mut world, Vec2::new(1.75, -1.), 0, body, Vec2::ZERO);
+    let block = spawn_body(&mut world, Vec2::ZERO, 1, body, Vec2::new(0.5, 0.));
+    n_body_collisions(&mut world);
+    let block = world.entities.get_entity(block).unwrap();
+    assert!(block.velocity.x < 0.5);
+    // Pushed back above its pivot, so the top turns back and the bottom carries on
+    assert!(block.angular_velocity < 0.);
+}
+
+#[test]
+fn damping_is_per_entity() {
+    use super::collisions::n_body_collisions;
+    let mut world = test_world();
+    let heavy = Body { linear_damping: 0.5, angular_damping: 0.25, ..Body::default() };
+    let ids = [Body::default(), heavy].map(|body| {
+        let id = spawn_body(&mut world, Vec2::new(10. * body.linear_damping, 0.), 0, body, Vec2::new(0.1, 0.));
+        world.entities.get_mut_entity(id).unwrap().angular_velocity = 0.1;
+        id
+    });
+    n_body_collisions(&mut world);
+    let [light, heavy] = ids.map(|id| world.entities.get_entity(id).unwrap());
+    assert!((light.velocity.x - 0.095).abs() < 1e-6 && (light.angular_velocity - 0.095).abs() < 1e-6);
+    assert!((heavy.velocity.x - 0.05).abs() < 1e-6 && (heavy.angular_velocity - 0.075).abs() < 1e-6);
+}
@@ -0,0 +1,164 @@
+use std::f32::consts::PI;
+use macroquad::math::Vec2;
+use crate::engine::grid::partition::{gate, CellData, center_to_edge};
+use crate::engine::entities::{Entity, EntityPool, Location, ID};
+use crate::engine::world::World;
+use super::broadphase;
+
+/// One of the owner's corners inside one of the target's sensor cells.
+#[derive(Debug, Clone, Copy)]
+pub struct Overlap {
+    pub owner: ID,
+    pub target: ID,
+    // The first corner found inside, in world space
+    pub point: Vec2,
+    pub cell: CellData,
+}
+impl Overlap {
+    pub fn same_cell(&self, other:&Self) -> bool {
+        self.owner == other.owner && self.target == other.target
+            && self.cell.cell == other.cell.cell && self.cell.pointer.height == other.cell.pointer.height
+    }
+}
+
+/// A sensor cell with a corner of another entity in it at some point during the tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Visit {
+    // When a corner was first and last seen inside
+    pub first: Overlap,
+    pub last: Overlap,
+    // Still inside at the end of the tick
+    pub inside: bool,
+}
+
+/// Where an entity was when the tick started.
+#[derive(Debug, Clone, Copy)]
+pub struct Pose {
+    pub id: ID,
+    pub position: Vec2,
+    pub rotation: f32,
+}
+impl Pose {
+    fn of(entity:&Entity) -> Self { Self { id: entity.id, position: entity.location.position, rotation: entity.rotation } }
+    fn pivot(&self, entity:&Entity) -> Vec2 { self.position + entity.mass.center_of_mass.rotate(Vec2::from_angle(self.rotation)) }
+    // Rotation wraps at 2PI so it turns whichever way round is shorter
+    fn turn(&self, entity:&Entity) -> f32 { (entity.rotation - self.rotation + PI).rem_euclid(PI * 2.) - PI }
+    // Partway from here to where the entity is now, the pivot moves in a straight line while the grid swings around it
+    fn towards(&self, entity:&Entity, t:f32) -> Self {
+        let rotation = self.rotation + self.turn(entity) * t;
+        let pivot = self.pivot(entity).lerp(entity.pivot(), t);
+        Self { position: pivot - entity.mass.center_of_mass.rotate(Vec2::from_angle(rotation)), rotation, ..*self }
+    }
+}
+
+/// Every entity's pose, taken before a tick moves them so sensors can follow them along the way.
+pub fn poses(entities:&EntityPool) -> Vec<Pose> { entities.iter().map(Pose::of).collect() }
+
+/// Every sensor cell a corner of another entity was in during the tick, each listed once however many corners were in it.
+/// Corners are followed from their start poses at most half a cell at a time, so a cell crossed within a tick still counts.
+/// Sensors don't stop anything, so unlike collisions kinematic entities set them off too.
+pub fn visits(world:&World, start:&[Pose]) -> Vec<Visit> {
+    let start_of = |entity:&Entity| start.iter().find(|pose| pose.id == entity.id).copied().unwrap_or(Pose::of(entity));
+    let pairs = broadphase::pairs_within(&world.entities, |entity| {
+        Some(broadphase::swept_bounds(entity, 0.)?.expand(start_of(entity).pivot(entity) - entity.pivot()))
+    });
+    let mut visits:Vec<Visit> = Vec::new();
+    for (a, b) in pairs {
+        let (a, b) = (world.entities.get_entity(a).unwrap(), world.entities.get_entity(b).unwrap());
+        let (start_a, start_b) = (start_of(a), start_of(b));
+        // How far a corner of either could have moved relative to the other
+        let travel = [(a, start_a), (b, start_b)].into_iter().map(|(entity, start)| {
+            let reach = broadphase::swept_bounds(entity, 0.).map_or(0., |aabb| aabb.radius().x);
+            start.pivot(entity).distance(entity.pivot()) + start.turn(entity).abs() * reach
+        }).sum::<f32>();
+        let step = a.location.min_cell_length.min(b.location.min_cell_length).min_element() / 2.;
+        let steps = (travel / step).ceil() as usize;
+        for i in 0 ..= steps {
+            let t = if steps == 0 { 1. } else { i as f32 / steps as f32 };
+            let (pose_a, pose_b) = (start_a.towards(a, t), start_b.towards(b, t));
+            for (owner, owner_pose, target, target_pose) in [(a, pose_a, b, pose_b), (b, pose_b, a, pose_a)] {
+                for overlap in corners_in_sensors(world, owner, owner_pose, target, target_pose) {
+                    match visits.iter_mut().find(|visit| visit.first.same_cell(&overlap)) {
+                        Some(visit) => { visit.last = overlap; visit.inside |= i == steps }
+                        None => visits.push(Visit { first: overlap, last: overlap, inside: i == steps }),
+                    }
+                }
+            }
+        }
+    }
+    visits
+}
+
+// Which way into the cell each corner is, by the same zorder as the corners
+const INWARD: [Vec2; 4] = [Vec2::new(1., 1.), Vec2::new(-1., 1.), Vec2::new(1., -1.), Vec2::new(-1., -1.)];
+
+fn corners_in_sensors(world:&World, owner:&Entity, owner_pose:Pose, target:&Entity, target_pose:Pose) -> Vec<Overlap> {
+    if !target.corners.iter().any(|corners| world.blocks.is_sensor_leaf(corners.leaf)) { return Vec::new() }
+    // Same frame as collisions, turned about the target's pivot until its grid lines up with the axes
+    let (target_pivot, align_target) = (target_pose.pivot(target), Vec2::from_angle(-target_pose.rotation));
+    let location = Location { position: target_pivot - target.mass.center_of_mass, ..target.location };
+    let owner_forward = Vec2::from_angle(owner_pose.rotation);
+    let offset = center_to_edge(owner.location.pointer.height, owner.location.min_cell_length);
+    let mut found = Vec::new();
+    for corners in owner.corners.iter() {
+        for (i, inward) in INWARD.iter().enumerate() {
+            if corners.mask & (1 << i) == 0 { continue }
+            let point = owner_pose.position + (corners.points[i] - offset).rotate(owner_forward);
+            let aligned = (point - target_pivot).rotate(align_target) + target_pivot;
+            // The corner's on the edge of whatever it's in, so it's in the quadrant its own cell is in
+            let inward = inward.rotate(owner_forward).rotate(align_target);
+            let quadrant = 2 * (inward.y > 0.) as usize + (inward.x > 0.) as usize;
+            let cell = gate::point_to_real_cells(&world.graph, location, aligned)[quadrant];
+            if !world.blocks.is_sensor_cell(cell) { continue }
+            found.push(Overlap { owner: owner.id, target: target.id, point, cell: cell.unwrap() });
+        }
+    }
+    found
+}
+
+// A world with a sensor block added after the default ones
+#[cfg(test)]
+fn sensor_world() -> (World, crate::engine::grid::dag::Leaf) {
+    use macroquad::color::YELLOW;
+    use crate::engine::blocks::{Block, CollisionType};
+    let mut world = super::test_world();
+    let sensor = world.graph.add_leaf();
+    assert_eq!(world.blocks.add(Block::new(YELLOW, CollisionType::Sensor)), sensor);
+    (world, sensor)
+}
+
+#[test]
+fn corners_pass_through_sensors() {
+    use std::sync::{Arc, Mutex};
+    use crate::engine::grid::dag::Leaf;
+    use super::{collisions::n_body_collisions, spawn_filled};
+    use super::events::OverlapPhase;
+    let (mut world, sensor) = sensor_world();
+    let (zone, ball) = (spawn_filled(&mut world, 3., sensor, 1), spawn_filled(&mut world, 0., Leaf(1), 0));
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let log = heard.clone();
+    world.collisions.on_block_overlap(sensor, move |_, event| log.lock().unwrap().push((event.phase, event.owner, event.target)));
+    world.entities.get_mut_entity(ball).unwrap().velocity.x = 0.5;
+    for _ in 0 .. 40 { n_body_collisions(&mut world) }
+    assert!(world.entities.get_entity(ball).unwrap().location.position.x > 4.5);
+    // The zone's one cell, so it's only entered once however many corners are in it
+    assert_eq!(*heard.lock().unwrap(), [(OverlapPhase::Enter, ball, zone), (OverlapPhase::Exit, ball, zone)]);
+}
+
+#[test]
+fn fast_corners_cross_sensors_within_a_tick() {
+    use crate::engine::grid::dag::Leaf;
+    use super::{collisions::n_body_collisions, spawn_filled};
+    use super::events::OverlapPhase;
+    let (mut world, sensor) = sensor_world();
+    let (zone, ball) = (spawn_filled(&mut world, 3., sensor, 1), spawn_filled(&mut world, 0., Leaf(1), 0));
+    // Starts and ends the tick clear of the zone on either side
+    world.entities.get_mut_entity(ball).unwrap().velocity.x = 5.;
+    n_body_collisions(&mut world);
+    let heard:Vec<_> = world.collisions.overlap_events().iter().map(|event| (event.phase, event.owner, event.target)).collect();
+    assert_eq!(heard, [(OverlapPhase::Enter, ball, zone), (OverlapPhase::Exit, ball, zone)]);
+    let exit = world.collisions.overlap_events()[1];
+    assert!(exit.point.x > 2. && exit.point.x < 4.);
+    n_body_collisions(&mut world);
+    assert!(world.collisions.overlap_events().is_empty());
+}
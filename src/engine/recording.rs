@@ -0,0 +1,97 @@
+use super::binary::{ByteReader, ByteWriter, has_magic};
+use super::grid::dag::LoadError;
+use super::input::{InputEvent, InputTrigger, RecordedTick, Modifiers, input_code, input_from_code, trigger_code, trigger_from_code};
+
+// A recording is the state to start from followed by the input of every tick after it.
+// Replaying it through the same bindings with fixed ticks ends up in exactly the same place.
+
+const RECORDING_MAGIC: &[u8; 4] = b"GGIR";
+// Bumped whenever the layout or the game's state changes, older recordings are refused rather than misread.
+// They used to be written with the save format's version, so every layout up to 4 shares those numbers and can't be told apart.
+// 5 has modifiers, trigger codes with axis values, the context stack and a state holding the palette, histories and contacts.
+const RECORDING_VERSION: u8 = 5;
+
+pub struct InputRecording {
+    // Whatever the game needs to restore before the first tick, opaque to the engine
+    pub state: Vec<u8>,
+    // The input context stack when recording started
+    pub contexts: Vec<String>,
+    pub ticks: Vec<RecordedTick>,
+}
+impl InputRecording {
+    pub fn save(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.versioned_header(RECORDING_MAGIC, RECORDING_VERSION);
+        writer.bytes(&self.state);
+        writer.varint(self.contexts.len() as u64);
+        for context in &self.contexts { writer.bytes(context.as_bytes()) }
+        writer.varint(self.ticks.len() as u64);
+        for tick in &self.ticks {
+            // Events without a code can't come from a bound key, so can't trigger anything on replay either
+            let coded:Vec<_> = tick.events.iter()
+                .filter_map(|event| Some((input_code(event.input)?, event)))
+                .collect();
+            writer.varint(coded.len() as u64);
+            for (code, event) in coded {
+                writer.varint(code);
+                writer.varint(trigger_code(event.trigger));
+                writer.varint(event.modifiers.bits() as u64);
+                // Only axes move by anything other than 1
+                if event.trigger == InputTrigger::Moved { writer.f32(event.value) }
+            }
+            writer.vec2(tick.cursor);
+        }
+        writer.finish()
+    }
+
+    pub fn load(data:&[u8]) -> Result<Self, LoadError> {
+        let mut reader = ByteReader::new(data);
+        let mut read = || {
+            if reader.versioned_header(RECORDING_MAGIC, RECORDING_VERSION)? != RECORDING_VERSION { return None }
+            let state = reader.bytes()?.to_vec();
+            let mut contexts = Vec::new();
+            for _ in 0 .. reader.varint()? { contexts.push(String::from_utf8(reader.bytes()?.to_vec()).ok()?) }
+            let mut ticks = Vec::new();
+            for _ in 0 .. reader.varint()? {
+                let mut events = Vec::new();
+                for _ in 0 .. reader.varint()? {
+                    let input = input_from_code(reader.varint()?)?;
+                    let trigger = trigger_from_code(reader.varint()?)?;
+                    let modifiers = Modifiers::from_bits(u8::try_from(reader.varint()?).ok()?)?;
+                    let mut event = InputEvent::new(input, trigger, modifiers);
+                    if trigger == InputTrigger::Moved { event.value = reader.f32()? }
+                    events.push(event);
+                }
+                ticks.push(RecordedTick { events, cursor: reader.vec2()? });
+            }
+            Some(Self { state, contexts, ticks })
+        };
+        read().ok_or(LoadError::Malformed)
+    }
+
+    pub fn is_recording(data:&[u8]) -> bool { has_magic(data, RECORDING_MAGIC) }
+}
+
+#[test]
+fn recordings_round_trip() {
+    use macroquad::input::{KeyCode, MouseButton};
+    use macroquad::math::Vec2;
+    use super::input::{Axis, InputType};
+    let recording = InputRecording {
+        state: vec![1, 2, 3],
+        contexts: vec!["gameplay".to_string(), "editor".to_string()],
+        ticks: vec![
+            RecordedTick { events: vec![InputEvent::new(InputType::Keyboard(KeyCode::D), InputTrigger::Hold(30), Modifiers::NONE)], cursor: Vec2::ZERO },
+            RecordedTick { events: vec![InputEvent::axis(Axis::WheelY, -2.5, Modifiers::CONTROL)], cursor: Vec2::new(1.5, -2.) },
+            RecordedTick { events: vec![InputEvent::new(InputType::Mouse(MouseButton::Left), InputTrigger::Released, Modifiers::SHIFT)], cursor: Vec2::ONE },
+        ],
+    };
+    let data = recording.save();
+    let loaded = InputRecording::load(&data).unwrap();
+    assert_eq!((loaded.state, loaded.contexts, loaded.ticks), (recording.state, recording.contexts, recording.ticks));
+    assert!(InputRecording::load(&data[.. 12]).is_err());
+    // Written before the context stack, whose layout can't be read as this one
+    let mut old = data.clone();
+    old[4] = 1;
+    assert!(matches!(InputRecording::load(&old), Err(LoadError::Malformed)));
+}
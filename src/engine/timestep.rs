@@ -0,0 +1,44 @@
+// Runs the simulation at a fixed rate however fast frames are rendered.
+// Frame time is banked in an accumulator and spent one tick length at a time.
+
+// A slow frame shouldn't make the next one slower, so time past this many ticks is dropped
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
+pub struct FixedTimestep {
+    tick_length: f64,
+    accumulator: f64,
+}
+impl FixedTimestep {
+    pub fn new(ticks_per_second:u32) -> Self {
+        Self {
+            tick_length: 1. / ticks_per_second.max(1) as f64,
+            accumulator: 0.,
+        }
+    }
+
+    /// Banks a frame's worth of seconds, returning how many ticks should run.
+    pub fn advance(&mut self, frame_time:f32) -> u32 {
+        self.accumulator += frame_time.max(0.) as f64;
+        let ticks = (self.accumulator / self.tick_length) as u32;
+        let ran = ticks.min(MAX_TICKS_PER_FRAME);
+        self.accumulator = if ticks > ran { 0. } else { self.accumulator - ran as f64 * self.tick_length };
+        ran
+    }
+
+    /// How far between the last two ticks the current frame is, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick_length).clamp(0., 1.) as f32
+    }
+}
+
+#[test]
+fn ticks_ignore_frame_rate() {
+    let mut fast = FixedTimestep::new(64);
+    let mut slow = FixedTimestep::new(64);
+    let fast_ticks:u32 = (0 .. 128).map(|_| fast.advance(1. / 128.)).sum();
+    let slow_ticks:u32 = (0 .. 32).map(|_| slow.advance(1. / 32.)).sum();
+    assert_eq!((fast_ticks, slow_ticks), (64, 64));
+    // A stall only catches up so far
+    assert_eq!(slow.advance(10.), MAX_TICKS_PER_FRAME);
+    assert_eq!(slow.alpha(), 0.);
+}
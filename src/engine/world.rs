@@ -0,0 +1,49 @@
+use macroquad::math::Vec2;
+use super::grid::dag::{SparseDirectedGraph, BasicNode};
+use super::entities::EntityPool;
+use super::blocks::BlockPalette;
+use super::camera::Camera;
+use super::physics::contacts::Contacts;
+use super::physics::events::CollisionEvents;
+
+pub type Graph = SparseDirectedGraph<BasicNode>;
+
+/// Everything a simulation runs on. Nothing here is global, so separate worlds never share state.
+pub struct World {
+    pub graph: Graph,
+    pub entities: EntityPool,
+    pub blocks: BlockPalette,
+    pub camera: Camera,
+    // Kept between ticks, but never saved since it's rebuilt from the first hit
+    pub contacts: Contacts,
+    pub collisions: CollisionEvents,
+}
+impl Default for World {
+    fn default() -> Self {
+        Self {
+            graph: Graph::new(4),
+            entities: EntityPool::new(),
+            blocks: BlockPalette::default(),
+            camera: Camera::new(Vec2::ZERO, 4.),
+            contacts: Contacts::default(),
+            collisions: CollisionEvents::default(),
+        }
+    }
+}
+
+#[test]
+fn worlds_are_independent() {
+    use super::entities::Entity;
+    use super::grid::dag::Leaf;
+    let mut live = World::default();
+    let mut preview = World::default();
+    let ids = [&mut live, &mut preview].map(|world| {
+        let entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::ZERO, 2);
+        world.entities.spawn(entity)
+    });
+    let solid = live.graph.leaf_pointer(Leaf(1)).unwrap();
+    let entity = live.entities.get_mut_entity(ids[0]).unwrap();
+    entity.edit_root(&mut live.graph, &live.blocks, |graph, root| graph.set_node(root, &[0, 0], solid)).unwrap();
+    assert_eq!(live.entities.get_entity(ids[0]).unwrap().corners.len(), 7);
+    assert_eq!(preview.entities.get_entity(ids[1]).unwrap().corners.len(), 1);
+}
@@ -0,0 +1,116 @@
+use serde::{Serialize, Deserialize};
+use macroquad::math::Vec2;
+use crate::engine::camera::NoOpSink;
+use crate::engine::entities::Entity;
+use crate::engine::input::{key_from_name, InputEvent, InputHandler, InputTrigger, InputType, Modifiers};
+use crate::engine::recording::InputRecording;
+use crate::engine::world::World;
+use crate::{set_key_binds, start_replay, tick, InputData};
+
+// Steps the simulation without a window and dumps the final state of every entity as json,
+// so collision behaviour can be regression tested on machines without a GPU.
+// Takes either a json scenario or an input recording, which plays back from the scene it was started in.
+
+#[derive(Deserialize)]
+struct Scenario {
+    entities: Vec<ScenarioEntity>,
+    ticks: u32,
+    // Index into entities of the one inputs act on, whether collisions can push an entity is up to its save
+    #[serde(default = "default_target")]
+    target: usize,
+    #[serde(default)]
+    inputs: Vec<ScriptedInput>,
+}
+// The player is conventionally spawned after the terrain
+fn default_target() -> usize { 1 }
+
+#[derive(Deserialize)]
+struct ScenarioEntity {
+    // Json or binary entity save
+    path: String,
+}
+
+/// Fires on every tick in from .. to, or only on from when to is left out.
+/// Only keys can be scripted, mouse bindings read the cursor which doesn't exist here.
+#[derive(Deserialize)]
+struct ScriptedInput {
+    key: String,
+    trigger: InputTrigger,
+    #[serde(default)]
+    modifiers: Modifiers,
+    from: u32,
+    to: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct EntityState {
+    // The entity's slot, which for a scenario is its index in the entity list
+    id: u32,
+    position: Vec2,
+    velocity: Vec2,
+    rotation: f32,
+    angular_velocity: f32,
+}
+
+/// Returns the exit code, the final states go to stdout and errors to stderr.
+pub fn run(scenario_path:&str) -> i32 {
+    match simulate(scenario_path) {
+        Ok(states) => {
+            println!("{}", serde_json::to_string_pretty(&states).unwrap());
+            0
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            1
+        }
+    }
+}
+
+fn simulate(scenario_path:&str) -> Result<Vec<EntityState>, String> {
+    let data = std::fs::read(scenario_path).map_err(|error| format!("Failed to read {scenario_path}: {error}"))?;
+    let mut world = World::default();
+    world.camera.set_sink(Box::new(NoOpSink));
+    // Never polled, so scripted or recorded inputs are the only ones
+    let mut input = set_key_binds();
+    let mut vars = InputData::default();
+    if InputRecording::is_recording(&data) {
+        let ticks = start_replay(&mut world, &mut input, &mut vars, &data).map_err(|error| format!("Invalid recording {scenario_path}: {error}"))?;
+        for _ in 0 .. ticks { tick(&mut world, &mut input, &mut vars) }
+    } else {
+        let scenario:Scenario = serde_json::from_slice(&data).map_err(|error| format!("Invalid scenario {scenario_path}: {error}"))?;
+        run_scenario(&mut world, &mut input, &mut vars, &scenario)?;
+    }
+
+    Ok(world.entities.iter().map(|entity| EntityState {
+        id: entity.id.index(),
+        position: entity.location.position,
+        velocity: entity.velocity,
+        rotation: entity.rotation,
+        angular_velocity: entity.angular_velocity,
+    }).collect())
+}
+
+fn run_scenario(world:&mut World, input:&mut InputHandler<InputData>, vars:&mut InputData, scenario:&Scenario) -> Result<(), String> {
+    let mut ids = Vec::new();
+    for entity in &scenario.entities {
+        let save_data = std::fs::read(&entity.path).map_err(|error| format!("Failed to read {}: {error}", entity.path))?;
+        let loaded = Entity::load_bytes(&mut world.graph, &mut world.blocks, save_data)
+            .map_err(|error| format!("Failed to load {}: {error}", entity.path))?;
+        ids.push(world.entities.spawn(loaded));
+    }
+    let mut inputs = Vec::new();
+    for scripted in &scenario.inputs {
+        let key = key_from_name(&scripted.key).ok_or(format!("Unknown key {}", scripted.key))?;
+        let event = InputEvent::new(InputType::Keyboard(key), scripted.trigger, scripted.modifiers);
+        inputs.push((event, scripted.from .. scripted.to.unwrap_or(scripted.from + 1)));
+    }
+
+    vars.target_id = *ids.get(scenario.target).ok_or(format!("There's no entity {} to target", scenario.target))?;
+    for tick_number in 0 .. scenario.ticks {
+        for (event, ticks) in &inputs {
+            if ticks.contains(&tick_number) { input.inject(*event) }
+        }
+        tick(world, input, vars);
+    }
+    Ok(())
+}
@@ -1,58 +1,35 @@
 mod engine;
-mod globals {
-    use crate::engine::blocks::BlockPalette;
-    use crate::engine::grid::dag::{SparseDirectedGraph, BasicNode};
-    use crate::engine::camera::Camera;
-    use macroquad::math::Vec2;
-    use crate::engine::entities::EntityPool;
-    use lazy_static::lazy_static;
-    use parking_lot::RwLock;
-    lazy_static! {
-        pub static ref GRAPH: RwLock<SparseDirectedGraph<BasicNode>> = RwLock::new(SparseDirectedGraph::<BasicNode>::new(4));
-        pub static ref ENTITIES: RwLock<EntityPool> = RwLock::new(EntityPool::new());
-        pub static ref CAMERA: RwLock<Camera> = RwLock::new(Camera::new(Vec2::ZERO, 4.));
-        pub static ref BLOCKS: BlockPalette = BlockPalette::default();
-    }
-}
-use globals::*;
+mod headless;
 use engine::input::*;
-use macroquad::math::Vec2;
-use macroquad::prelude::{mouse_position, KeyCode, MouseButton};
+use macroquad::math::{Vec2, UVec2};
+use macroquad::prelude::{mouse_position, is_key_pressed, KeyCode};
+use macroquad::color::hsl_to_rgb;
 use std::f32::consts::PI;
+use std::collections::HashMap;
 use engine::{
     physics::collisions::n_body_collisions,
+    timestep::FixedTimestep,
+    recording::InputRecording,
+    binary::{ByteReader, ByteWriter},
     entities::{Entity, ID, Location},
+    world::{World, Graph},
     math::Aabb,
-    grid::dag::{Index, ExternalPointer},
-    grid::partition::{gate, ZorderPath},
+    blocks::{Block, BlockPalette, CollisionType},
+    physics::contacts::Contacts,
+    grid::dag::{Leaf, ExternalPointer, LoadError},
+    grid::partition::{gate, ZorderPath, cell_length, center_to_edge},
+    grid::brush,
 };
 
-use std::time::Duration;
-use std::thread;
-fn init_deadlock_detection() {
-    thread::spawn(move || {
-        loop {
-            thread::sleep(Duration::from_secs(3));
-            let deadlocks = parking_lot::deadlock::check_deadlock();
-            if !deadlocks.is_empty() {
-                println!("{} deadlocks detected", deadlocks.len());
-                for deadlock in deadlocks {
-                    println!("Deadlock threads:");
-                    for thread in deadlock {
-                        println!("Thread Id {:#?}", thread.thread_id());
-                        println!("Backtrace:\n{:#?}", thread.backtrace());
-                    }
-                }
-                panic!("Deadlock detected");
-            }
-        }
-    });
-}
-
+// Movement is applied once per physics tick
 const SPEED: f32 = 0.005;
 const ROTATION_SPEED: f32 = PI/512.;
-const MAX_COLOR: usize = 4;
 const MAX_HEIGHT: u32 = 4;
+const TICKS_PER_SECOND: u32 = 60;
+const RECORDING_PATH: &str = "data/recording.bin";
+const KEYMAP_PATH: &str = "data/keymap.json";
+// Built in so headless runs and the web build don't depend on whatever the keymap file has been edited to
+const DEFAULT_KEYMAP: &str = include_str!("../data/keymap.json");
 
 fn set_panic_hook() {
     std::panic::set_hook(Box::new(|panic_info| {
@@ -77,228 +54,646 @@ fn set_panic_hook() {
     }));
 }
 
+// A broken save shouldn't stop the game from starting, the entity just starts out empty
+fn load_or_blank(world:&mut World, data:String, name:&str) -> Entity {
+    Entity::load(&mut world.graph, &mut world.blocks, data).unwrap_or_else(|error| {
+        eprintln!("Failed to load {name}: {error}");
+        Entity::blank(&mut world.graph, &world.blocks, Vec2::ZERO, 3)
+    })
+}
+
 fn mouse_pos() -> Vec2 { Vec2::from(mouse_position()) }
 use macroquad::color::*;
 
-#[macroquad::main("Window")]
-async fn main() {
+fn main() {
+    // `--headless <scenario.json>` steps the simulation without ever opening a window
+    let args:Vec<String> = std::env::args().collect();
+    if let Some(flag) = args.iter().position(|arg| arg == "--headless") {
+        let Some(scenario) = args.get(flag + 1) else {
+            eprintln!("--headless needs a scenario file");
+            std::process::exit(2);
+        };
+        std::process::exit(headless::run(scenario));
+    }
+    // `--tick-rate <n>` changes how many physics ticks run each second
+    let tick_rate = args.iter().position(|arg| arg == "--tick-rate")
+        .and_then(|flag| args.get(flag + 1)?.parse().ok())
+        .unwrap_or(TICKS_PER_SECOND);
+    // `--replay <recording>` plays a recording back before handing control over
+    let replay = args.iter().position(|arg| arg == "--replay").and_then(|flag| args.get(flag + 1).cloned());
+    macroquad::Window::new("Window", window_main(tick_rate, replay));
+}
+
+// One step of the simulation, shared by the window and headless modes
+pub fn tick(world:&mut World, input:&mut InputHandler<InputData>, vars:&mut InputData) {
+    world.entities.store_poses();
+    vars.cursor = input.cursor();
+    vars.replaying = input.is_replaying();
+    input.handle(vars, world);
+    n_body_collisions(world);
+}
+
+// Loads the keymap file whenever it changes, a broken edit keeps the old bindings
+fn reload_keymap(input:&mut InputHandler<InputData>, last_modified:&mut Option<std::time::SystemTime>) {
+    let Ok(modified) = std::fs::metadata(KEYMAP_PATH).and_then(|metadata| metadata.modified()) else { return };
+    if *last_modified == Some(modified) { return }
+    *last_modified = Some(modified);
+    let Ok(keymap) = std::fs::read_to_string(KEYMAP_PATH) else { return };
+    if let Err(error) = input.load_keymap(&keymap) { eprintln!("Failed to load {KEYMAP_PATH}: {error}") }
+}
+
+/// Restores the state a recording starts from and queues its input, returning how many ticks it lasts.
+pub fn start_replay(world:&mut World, input:&mut InputHandler<InputData>, vars:&mut InputData, data:&[u8]) -> Result<usize, LoadError> {
+    let recording = InputRecording::load(data)?;
+    vars.restore(world, &recording.state)?;
+    input.set_contexts(&recording.contexts);
+    let ticks = recording.ticks.len();
+    input.replay(recording.ticks);
+    Ok(ticks)
+}
+
+async fn window_main(tick_rate:u32, replay:Option<String>) {
     if !cfg!(target_arch = "wasm32") {
         set_panic_hook();
-        init_deadlock_detection();
     }
     #[cfg(debug_assertions)]
     println!("Debug mode");
     #[cfg(not(debug_assertions))]
     println!("Release mode");
     macroquad::window::request_new_screen_size(1024., 1024.);
+    let mut world = World::default();
+    let mut vars = InputData::default();
     // Load entities 
     {
-        let mut entity_pool = ENTITIES.write();
         let terrain_string = if cfg!(target_arch = "wasm32") { 
             String::from_utf8(include_bytes!("../data/terrain.json").as_ref().to_vec()).unwrap_or_default()
         } else {
             std::fs::read_to_string("data/terrain.json").unwrap_or_default()
         };
-        entity_pool.add_to_pool(
-            Entity::load(terrain_string, 0)
-        );
+        let terrain = load_or_blank(&mut world, terrain_string, "terrain");
+        let terrain = world.entities.spawn(terrain);
         let player_string = if cfg!(target_arch = "wasm32") { 
             String::from_utf8(include_bytes!("../data/player.json").as_ref().to_vec()).unwrap_or_default()
         } else {
             std::fs::read_to_string("data/player.json").unwrap_or_default()
         };
-        entity_pool.add_to_pool(
-            Entity::load(player_string, 1)
-        );
+        let player = load_or_blank(&mut world, player_string, "player");
+        let player = world.entities.spawn(player);
+        vars.target_id = player;
+        vars.file_paths.insert(terrain, "data/terrain".to_string());
+        vars.file_paths.insert(player, "data/player".to_string());
     }
     
-    let mut vars = InputData::default();
     let mut input = set_key_binds();
+    let mut timestep = FixedTimestep::new(tick_rate);
+    if let Some(path) = replay {
+        let started = std::fs::read(&path).map_err(|error| error.to_string())
+            .and_then(|data| start_replay(&mut world, &mut input, &mut vars, &data).map_err(|error| error.to_string()));
+        if let Err(error) = started { eprintln!("Failed to replay {path}: {error}") }
+    }
+    // The state and input contexts recording started from, while recording
+    let mut recording_start = None;
+    let mut keymap_modified = None;
     
     loop {
-        let old_pos = { // Drop entities after reading from it
-            let entities = ENTITIES.read();
-            entities.draw_all(vars.render_rotated, vars.render_debug);
+        if !cfg!(target_arch = "wasm32") { reload_keymap(&mut input, &mut keymap_modified) }
+        // Recording controls the handler itself, so it can't be one of its bindings
+        if !cfg!(target_arch = "wasm32") && is_key_pressed(KeyCode::F6) && !input.is_replaying() {
+            match input.stop_recording() {
+                Some(ticks) => {
+                    let (state, contexts) = recording_start.take().unwrap();
+                    let recording = InputRecording { state, contexts, ticks };
+                    std::fs::write(RECORDING_PATH, recording.save()).unwrap();
+                }
+                None => {
+                    recording_start = Some((vars.snapshot(&world), input.contexts().to_vec()));
+                    input.start_recording();
+                }
+            }
+        }
+        input.poll(world.camera.screen_to_world(mouse_pos()));
+        let old_pos = {
+            let (entities, camera) = (&world.entities, &world.camera);
+            let alpha = timestep.alpha();
+            entities.draw_all(camera, &world.blocks, vars.render_rotated, vars.render_debug, alpha);
             let target = entities.get_entity(vars.target_id()).unwrap();
-            target.draw_outline(macroquad::color::DARKBLUE);
-            // let location = entities.get_entity((vars.target_id() + 1) % 2).unwrap().location;
-            // if let Some(aabb) = target.aabb() { 
-            //     aabb.overlaps(location);
-            //     CAMERA.read().outline_bounds(aabb, 0.3, macroquad::color::DARKBLUE);
-            // }
+            target.draw_outline(&world.graph, &world.blocks, camera, macroquad::color::DARKBLUE, alpha);
+            if let Some(anchor) = vars.brush_anchor {
+                camera.draw_vec_line(anchor, input.cursor(), macroquad::color::DARKBLUE);
+            }
+            if vars.render_debug && let Some(aabb) = target.aabb() {
+                for other in entities.iter().filter(|entity| entity.id != target.id) {
+                    aabb.overlaps(&world, other);
+                }
+                camera.outline_bounds(aabb, 0.3, macroquad::color::DARKBLUE);
+            }
             // We want to move the camera to where the target is drawn, not where the target is moved to.
-            target.location.position
+            target.interpolated_pose(alpha).0
         };
         
-        
-        input.handle(&mut vars);
-        
-        n_body_collisions((vars.target_id() + 1) % 2);
+        // However long the frame took, the simulation only ever moves in whole ticks
+        for _ in 0 .. timestep.advance(macroquad::time::get_frame_time()) {
+            tick(&mut world, &mut input, &mut vars);
+        }
         
         // We don't want to move the camera until after we've drawn all the collision debug.
         // This ensures everything lines up with the current frame.
-        CAMERA.write().update(Some((old_pos, 0.4)));
+        world.camera.update(Some((old_pos, 0.4)));
         macroquad::window::next_frame().await
     }
 
 }
 
 impl Aabb {
-    pub fn overlaps(&self, location:Location) {
-        let top_left = self.min();
-        let bottom_right = self.max();
-        let corners = [
-            top_left,
-            Vec2::new(bottom_right.x, top_left.y),
-            bottom_right,
-            Vec2::new(top_left.x, bottom_right.y),
-        ];
-        let cells = corners.iter()
-            .filter_map(|corner| gate::point_to_real_cells(location, *corner)[0]);
-        let points = cells.map(|cell| {
-            cell.to_point(location, Vec2::ONE)
-        });
-        for point in points {
-            CAMERA.read().draw_point(point, 0.2, Color::from_rgba(255, 0, 0, 150));
+    /// Outlines every cell of entity which overlaps self (in world space)
+    pub fn overlaps(&self, world:&World, entity:&Entity) {
+        let location = entity.location;
+        let offset = center_to_edge(location.pointer.height, location.min_cell_length);
+        let to_local = |point:Vec2| (point - location.position).rotate(Vec2::from_angle(-entity.rotation)) + offset;
+        let to_world = |point:Vec2| (point - offset).rotate(entity.forward) + location.position;
+        // The entity may be rotated, so we query with the local bounds of all four corners
+        let corners = [self.min(), Vec2::new(self.max().x, self.min().y), self.max(), Vec2::new(self.min().x, self.max().y)].map(to_local);
+        let local = Aabb::from_bounds(
+            corners.iter().copied().reduce(Vec2::min).unwrap(),
+            corners.iter().copied().reduce(Vec2::max).unwrap(),
+        );
+        for cell in ZorderPath::cells_intersecting_aabb(&world.graph, &world.blocks, location.pointer, local, location.min_cell_length) {
+            let length = cell_length(cell.pointer.height, location.min_cell_length);
+            let top_left = cell.cell.as_vec2() * length;
+            let points = [
+                top_left,
+                top_left.with_x(top_left.x + length.x),
+                top_left + length,
+                top_left.with_y(top_left.y + length.y),
+            ].map(to_world);
+            world.camera.draw_outline(&points, Color::from_rgba(255, 0, 0, 150));
         }
     }
 }
 
-pub fn set_grid_cell(entity:ID, world_point:Vec2, new_cell:ExternalPointer) {
-    let mut entities = ENTITIES.write();
-    let entity = &mut entities.get_mut_entity(entity).unwrap();
+// Undoes the entity's rotation, then finds the cell at height containing world_point.
+// Turning about the pivot keeps location.position on the grid's center, so that's what the rotation is undone about
+fn grid_cell(location:Location, rotation:f32, world_point:Vec2, height:u32) -> Option<UVec2> {
+    let rotated_point = (world_point - location.position).rotate(Vec2::from_angle(-rotation)) + location.position;
+    gate::point_to_cells(location, height, rotated_point)[0]
+}
+
+pub fn set_grid_cell(world:&mut World, entity:ID, world_point:Vec2, new_cell:ExternalPointer) {
+    let entity = world.entities.get_mut_entity(entity).unwrap();
     if new_cell.height > entity.location.pointer.height { return; }
     
-    let rotated_point = (world_point - entity.location.position).rotate(Vec2::from_angle(-entity.rotation)) + entity.location.position;
-    
-    let Some(cell) = gate::point_to_cells(entity.location, new_cell.height, rotated_point)[0] else { return };
+    let Some(cell) = grid_cell(entity.location, entity.rotation, world_point, new_cell.height) else { return };
     let path = ZorderPath::from_cell(cell, entity.location.pointer.height - new_cell.height);
-    let Ok(root) = GRAPH.write().set_node(entity.location.pointer, &path.steps(), new_cell.pointer) else {
+    if entity.edit_root(&mut world.graph, &world.blocks, |graph, root| graph.set_node(root, &path.steps(), new_cell.pointer)).is_err() {
         dbg!("Failed to set cell");
-        return;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Cell,
+    Rectangle,
+    Circle,
+    Line,
+    Fill,
+}
+impl BrushShape {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Cell => Self::Rectangle,
+            Self::Rectangle => Self::Circle,
+            Self::Circle => Self::Line,
+            Self::Line => Self::Fill,
+            Self::Fill => Self::Cell,
+        }
+    }
+}
+impl TryFrom<u64> for BrushShape {
+    type Error = LoadError;
+    fn try_from(value:u64) -> Result<Self, LoadError> {
+        Ok(match value {
+            0 => Self::Cell,
+            1 => Self::Rectangle,
+            2 => Self::Circle,
+            3 => Self::Line,
+            4 => Self::Fill,
+            _ => return Err(LoadError::Malformed),
+        })
+    }
+}
+
+/// Paints the shape spanning from -> to (both world points) in a single batched write.
+/// Fill ignores from and floods the region under to.
+pub fn paint_brush(world:&mut World, entity:ID, shape:BrushShape, from:Vec2, to:Vec2, new_cell:ExternalPointer) {
+    let entity = world.entities.get_mut_entity(entity).unwrap();
+    if new_cell.height > entity.location.pointer.height { return; }
+    let depth = entity.location.pointer.height - new_cell.height;
+    let (location, rotation) = (entity.location, entity.rotation);
+    let paths = if shape == BrushShape::Fill {
+        let Some(seed) = grid_cell(location, rotation, to, 0) else { return };
+        brush::flood_fill(&world.graph, location.pointer, seed)
+    } else {
+        let Some(start) = grid_cell(location, rotation, from, new_cell.height) else { return };
+        let Some(end) = grid_cell(location, rotation, to, new_cell.height) else { return };
+        match shape {
+            BrushShape::Cell => vec![ZorderPath::from_cell(end, depth)],
+            BrushShape::Rectangle => brush::rectangle(start, end, depth),
+            BrushShape::Circle => brush::circle(start.as_vec2() + 0.5, start.as_vec2().distance(end.as_vec2()), depth),
+            BrushShape::Line => brush::line(start, end, depth),
+            BrushShape::Fill => unreachable!(),
+        }
     };
-    entity.set_root(root);
+    let writes:Vec<_> = paths.into_iter().map(|path| (path, new_cell.pointer)).collect();
+    if entity.edit_root(&mut world.graph, &world.blocks, |graph, root| graph.set_nodes(root, &writes)).is_err() {
+        dbg!("Failed to paint brush");
+    }
+}
+
+/// Adds a leaf to the graph and a matching block to the palette, so the two stay in step.
+pub fn add_material(world:&mut World, collision_type:CollisionType) -> Leaf {
+    let leaf = world.graph.add_leaf();
+    // Golden ratio hue steps keep consecutive materials visually distinct
+    let color = hsl_to_rgb((leaf.0 as f32 * 0.618_034).fract(), 0.6, 0.5);
+    assert_eq!(world.blocks.add(Block::new(color, collision_type)), leaf, "Palette and graph leaves are out of sync");
+    leaf
 }
 
 pub trait DataAccess {
     fn target_id(&self) -> ID;
-    fn edit_color(&self) -> usize;
+    fn edit_color(&self) -> Leaf;
     fn edit_height(&self) -> u32;
-    fn file_paths(&self) -> &[String; 2];
+    fn file_path(&self, id:ID) -> String;
 }
 pub struct InputData {
     pub target_id : ID,
-    pub edit_color : usize,
+    pub edit_color : Leaf,
     pub edit_height : u32,
     pub render_debug : bool,
     pub render_rotated: bool,
-    pub file_paths : [String; 2],
+    // Extensionless, binary saves go to .bin and readable exports to .json.
+    // Entities without one save under their slot index
+    pub file_paths : HashMap<ID, String>,
+    pub scene_path : String,
+    pub brush : BrushShape,
+    // Where the current rectangle/circle/line drag started
+    pub brush_anchor : Option<Vec2>,
+    // The entity the current stroke started on, which keeps it even if the target changes mid-stroke
+    pub stroke_target : Option<ID>,
+    // World position of the cursor for this tick, taken from the input handler so replays paint in the same place
+    pub cursor : Vec2,
+    // Whether this tick's input is being replayed, which shouldn't write over any files
+    pub replaying : bool,
+}
+impl InputData {
+    pub fn edit_cell(&self, graph:&Graph) -> ExternalPointer {
+        ExternalPointer::new(graph.leaf_pointer(self.edit_color).unwrap(), self.edit_height)
+    }
+
+    /// The scene along with the palette, edit histories, resting contacts and edit settings, everything a recording has to start from.
+    pub fn snapshot(&self, world:&World) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        world.blocks.write(&mut writer);
+        writer.bytes(&world.save_scene(self.target_id));
+        // Histories and contacts go by each entity's place in the scene
+        let order = world.entities.ids();
+        let stacks:Vec<_> = world.entities.iter().map(|entity| entity.history.stacks()).collect();
+        for (undo, redo) in &stacks {
+            writer.varint(undo.len() as u64);
+            writer.varint(redo.len() as u64);
+        }
+        let roots:Vec<_> = stacks.into_iter().flat_map(|(undo, redo)| undo.into_iter().chain(redo)).collect();
+        world.graph.write_forest(&mut writer, &roots);
+        world.contacts.write(&mut writer, &order);
+        writer.varint(self.edit_color.0 as u64);
+        writer.varint(self.edit_height as u64);
+        writer.varint(self.brush as u64);
+        writer.finish()
+    }
+
+    /// Nothing changes unless the whole snapshot loads.
+    pub fn restore(&mut self, world:&mut World, data:&[u8]) -> Result<(), LoadError> {
+        let mut reader = ByteReader::new(data);
+        let blocks = BlockPalette::read(&mut reader).ok_or(LoadError::Malformed)?;
+        // A graph with only the palette's leaves, so materials added during the replay get the leaves they had while recording
+        let mut staged = World { graph: Graph::new(blocks.leaf_count() as u16), blocks, ..World::default() };
+        let target = staged.load_scene(reader.bytes().ok_or(LoadError::Malformed)?)?;
+        let order = staged.entities.ids();
+        let lengths = order.iter()
+            .map(|_| Some((usize::try_from(reader.varint()?).ok()?, usize::try_from(reader.varint()?).ok()?)))
+            .collect::<Option<Vec<_>>>().ok_or(LoadError::Malformed)?;
+        let mut roots = staged.graph.read_forest(&mut reader, &HashMap::new())?.into_iter();
+        let root_count = lengths.iter().map(|(undo, redo)| undo.saturating_add(*redo)).fold(0, usize::saturating_add);
+        if roots.len() != root_count { return Err(LoadError::Malformed) }
+        for (id, (undo, redo)) in order.iter().zip(lengths) {
+            let (undo, redo) = (roots.by_ref().take(undo).collect(), roots.by_ref().take(redo).collect());
+            staged.entities.get_mut_entity(*id).unwrap().history.set_stacks(&mut staged.graph, undo, redo);
+        }
+        staged.contacts = Contacts::read(&mut reader, &order).ok_or(LoadError::Malformed)?;
+        let mut read_settings = || {
+            let color = u16::try_from(reader.varint()?).ok().filter(|color| (*color as usize) < staged.blocks.leaf_count())?;
+            let height = u32::try_from(reader.varint()?).ok().filter(|height| *height < MAX_HEIGHT)?;
+            Some((color, height, reader.varint()?))
+        };
+        let (color, height, brush) = read_settings().ok_or(LoadError::Malformed)?;
+        let brush = BrushShape::try_from(brush)?;
+        // The camera's sink and the event listeners stay with the world
+        world.graph = staged.graph;
+        world.entities = staged.entities;
+        world.blocks = staged.blocks;
+        world.contacts = staged.contacts;
+        world.collisions.reset();
+        world.camera.set_view(staged.camera.position(), staged.camera.radius());
+        self.target_id = target;
+        self.edit_color = Leaf(color);
+        self.edit_height = height;
+        self.brush = brush;
+        self.brush_anchor = None;
+        self.stroke_target = None;
+        Ok(())
+    }
 }
 impl Default for InputData {
     fn default() -> Self {
         Self {
-            target_id: 1,
-            edit_color: 0,
+            // Nothing until an entity is spawned to be the target
+            target_id: ID::default(),
+            edit_color: Leaf(0),
             edit_height: 0,
             render_debug: true,
             render_rotated: true,
-            file_paths: ["data/terrain.json".to_string(), "data/player.json".to_string()],
+            file_paths: HashMap::new(),
+            scene_path: "data/scene.bin".to_string(),
+            brush: BrushShape::Cell,
+            brush_anchor: None,
+            stroke_target: None,
+            cursor: Vec2::ZERO,
+            replaying: false,
         }
     }
 }
 impl DataAccess for InputData {
     fn target_id(&self) -> ID { self.target_id }
-    fn edit_color(&self) -> usize { self.edit_color }
+    fn edit_color(&self) -> Leaf { self.edit_color }
     fn edit_height(&self) -> u32 { self.edit_height }
-    fn file_paths(&self) -> &[String; 2] { &self.file_paths }
+    fn file_path(&self, id:ID) -> String {
+        self.file_paths.get(&id).cloned().unwrap_or_else(|| format!("data/entity_{}", id.index()))
+    }
 }
 
 pub fn set_key_binds() -> InputHandler<InputData> {
     let mut input = InputHandler::new();
     // Movement
-    input.bind_key(KeyCode::W, InputTrigger::Down, |data : &mut InputData| {
+    input.add_action("move_up", |data : &mut InputData, world : &mut World| {
         let id = data.target_id();
-        ENTITIES.write().get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(0., -SPEED));
+        world.entities.get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(0., -SPEED));
     });
-    input.bind_key(KeyCode::S, InputTrigger::Down, |data : &mut InputData| {
+    input.add_action("move_down", |data : &mut InputData, world : &mut World| {
         let id = data.target_id();
-        ENTITIES.write().get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(0., SPEED));
+        world.entities.get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(0., SPEED));
     });
-    input.bind_key(KeyCode::A, InputTrigger::Down, |data : &mut InputData| {
+    input.add_action("move_left", |data : &mut InputData, world : &mut World| {
         let id = data.target_id();
-        ENTITIES.write().get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(-SPEED, 0.));
+        world.entities.get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(-SPEED, 0.));
     });
-    input.bind_key(KeyCode::D, InputTrigger::Down, |data : &mut InputData| {
+    input.add_action("move_right", |data : &mut InputData, world : &mut World| {
         let id = data.target_id();
-        ENTITIES.write().get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(SPEED, 0.));
+        world.entities.get_mut_entity(id).unwrap().apply_abs_velocity(Vec2::new(SPEED, 0.));
     });
-    input.bind_key(KeyCode::Q, InputTrigger::Down, |data : &mut InputData| {
+    input.add_action("rotate_left", |data : &mut InputData, world : &mut World| {
         let id = data.target_id();
-        ENTITIES.write().get_mut_entity(id).unwrap().angular_velocity -= ROTATION_SPEED;
+        world.entities.get_mut_entity(id).unwrap().angular_velocity -= ROTATION_SPEED;
     });
-    input.bind_key(KeyCode::E, InputTrigger::Down, |data : &mut InputData| {
+    input.add_action("rotate_right", |data : &mut InputData, world : &mut World| {
         let id = data.target_id();
-        ENTITIES.write().get_mut_entity(id).unwrap().angular_velocity += ROTATION_SPEED;
+        world.entities.get_mut_entity(id).unwrap().angular_velocity += ROTATION_SPEED;
     });
-    input.bind_key(KeyCode::Space, InputTrigger::Down, |data : &mut InputData| {
-        ENTITIES.write().get_mut_entity(data.target_id()).unwrap().stop();
+    input.add_action("stop", |data : &mut InputData, world : &mut World| {
+        world.entities.get_mut_entity(data.target_id()).unwrap().stop();
     });
 
     // Editing
-    input.bind_key(KeyCode::V, InputTrigger::Pressed, |data : &mut InputData| {
+    input.add_action("cycle_color", |data : &mut InputData, world : &mut World| {
         let color = &mut data.edit_color;
-        *color = (*color + 1) % MAX_COLOR;
+        *color = Leaf((color.0 + 1) % world.blocks.leaf_count() as u16);
+    });
+    input.add_action("new_material", |data : &mut InputData, world : &mut World| {
+        data.edit_color = add_material(world, CollisionType::Solid);
+    });
+    input.add_action("new_sensor", |data : &mut InputData, world : &mut World| {
+        data.edit_color = add_material(world, CollisionType::Sensor);
     });
-    input.bind_key(KeyCode::B, InputTrigger::Pressed, |data : &mut InputData| {
+    input.add_action("cycle_height", |data : &mut InputData, _world : &mut World| {
         let height = &mut data.edit_height;
         *height = (*height + 1) % MAX_HEIGHT;
     });
-    input.bind_mouse(MouseButton::Left, InputTrigger::Down, |data : &mut InputData| {
-        set_grid_cell(
-            data.target_id,
-            CAMERA.read().screen_to_world(mouse_pos()),
-            ExternalPointer::new(Index(data.edit_color), data.edit_height)
-        );
+    input.add_action("cycle_brush", |data : &mut InputData, _world : &mut World| {
+        data.brush = data.brush.next();
+        data.brush_anchor = None;
+    });
+    input.add_action("paint", |data : &mut InputData, world : &mut World| {
+        if data.brush != BrushShape::Cell { return }
+        // Keeps to the entity the stroke started on, whose group is the one open
+        let target = data.stroke_target.unwrap_or(data.target_id);
+        if world.entities.get_entity(target).is_none() { return }
+        let point = data.cursor;
+        let new_cell = data.edit_cell(&world.graph);
+        set_grid_cell(world, target, point, new_cell);
+    });
+    input.add_action("paint_start", |data : &mut InputData, world : &mut World| {
+        // A whole stroke is undone at once
+        world.entities.get_mut_entity(data.target_id).unwrap().history.begin_group();
+        data.stroke_target = Some(data.target_id);
+        let point = data.cursor;
+        match data.brush {
+            BrushShape::Cell => {},
+            BrushShape::Fill => paint_brush(world, data.target_id, data.brush, point, point, data.edit_cell(&world.graph)),
+            _ => data.brush_anchor = Some(point),
+        }
+    });
+    input.add_action("paint_end", |data : &mut InputData, world : &mut World| {
+        // The stroke's entity may have been despawned since
+        let Some(stroke_target) = data.stroke_target.take().filter(|id| world.entities.get_entity(*id).is_some()) else {
+            data.brush_anchor = None;
+            return
+        };
+        if let Some(anchor) = data.brush_anchor.take() {
+            let point = data.cursor;
+            let new_cell = data.edit_cell(&world.graph);
+            paint_brush(world, stroke_target, data.brush, anchor, point, new_cell);
+        }
+        world.entities.get_mut_entity(stroke_target).unwrap().history.end_group();
+    });
+    input.add_action("undo", |data : &mut InputData, world : &mut World| {
+        world.entities.get_mut_entity(data.target_id).unwrap().undo(&mut world.graph, &world.blocks);
+    });
+    input.add_action("redo", |data : &mut InputData, world : &mut World| {
+        world.entities.get_mut_entity(data.target_id).unwrap().redo(&mut world.graph, &world.blocks);
     });
-    input.bind_key(KeyCode::F, InputTrigger::Pressed, |data : &mut InputData| {
-        ENTITIES.write().get_mut_entity(data.target_id).unwrap().stop();
-        data.target_id = (data.target_id + 1) % 2;
+    input.add_action("switch_target", |data : &mut InputData, world : &mut World| {
+        world.entities.get_mut_entity(data.target_id).unwrap().stop();
+        data.target_id = world.entities.next_id(data.target_id).unwrap();
+    });
+
+    // Entities
+    input.add_action("spawn", |data : &mut InputData, world : &mut World| {
+        let entity = Entity::blank(&mut world.graph, &world.blocks, data.cursor, 3);
+        world.entities.get_mut_entity(data.target_id).unwrap().stop();
+        data.target_id = world.entities.spawn(entity);
+    });
+    input.add_action("despawn", |data : &mut InputData, world : &mut World| {
+        // There always has to be something to target
+        if world.entities.len() == 1 { return }
+        world.entities.despawn(data.target_id).unwrap().release(&mut world.graph);
+        data.file_paths.remove(&data.target_id);
+        data.target_id = world.entities.next_id(data.target_id).unwrap();
+    });
+    input.add_action("toggle_kinematic", |data : &mut InputData, world : &mut World| {
+        let entity = world.entities.get_mut_entity(data.target_id).unwrap();
+        entity.kinematic = !entity.kinematic;
     });
     
     // Save/Load
     if !cfg!(target_arch = "wasm32") {
-        input.bind_key(KeyCode::K, InputTrigger::Pressed, |data : &mut InputData| {
-            let save_data = ENTITIES.read().save_entity(data.target_id);
-            std::fs::write(&data.file_paths[data.target_id as usize], save_data).unwrap();
+        input.add_action("save", |data : &mut InputData, world : &mut World| {
+            if data.replaying { return }
+            let save_data = world.entities.save_entity_binary(&world.graph, &world.blocks, data.target_id);
+            std::fs::write(format!("{}.bin", data.file_path(data.target_id)), save_data).unwrap();
         });
-        input.bind_key(KeyCode::L, InputTrigger::Pressed, |data : &mut InputData| {
-            let mut entities = ENTITIES.write();
-            let Ok(save_data) = std::fs::read_to_string(&data.file_paths[data.target_id as usize]) else {
+        input.add_action("export", |data : &mut InputData, world : &mut World| {
+            if data.replaying { return }
+            let save_data = world.entities.save_entity(&world.graph, &world.blocks, data.target_id);
+            std::fs::write(format!("{}.json", data.file_path(data.target_id)), save_data).unwrap();
+        });
+        input.add_action("load", |data : &mut InputData, world : &mut World| {
+            let path = data.file_path(data.target_id);
+            // Prefer the binary save, falling back to the readable one
+            let Ok(save_data) = std::fs::read(format!("{path}.bin")).or_else(|_| std::fs::read(format!("{path}.json"))) else {
                 dbg!("No save data found");
                 return;
             };
-            *entities.get_mut_entity(data.target_id).unwrap() = Entity::load(save_data, data.target_id)
+            let mut loaded = match Entity::load_bytes(&mut world.graph, &mut world.blocks, save_data) {
+                Ok(loaded) => loaded,
+                Err(error) => { eprintln!("Failed to load {path}: {error}"); return }
+            };
+            let entity = world.entities.get_mut_entity(data.target_id).unwrap();
+            entity.release(&mut world.graph);
+            loaded.id = entity.id;
+            *entity = loaded
+        });
+        input.add_action("save_scene", |data : &mut InputData, world : &mut World| {
+            if data.replaying { return }
+            let save_data = world.save_scene(data.target_id);
+            std::fs::write(&data.scene_path, save_data).unwrap();
         });
+        input.add_action("load_scene", |data : &mut InputData, world : &mut World| {
+            let Ok(save_data) = std::fs::read(&data.scene_path) else {
+                dbg!("No scene found");
+                return;
+            };
+            match world.load_scene(&save_data) {
+                Ok(target) => data.target_id = target,
+                Err(error) => eprintln!("Failed to load {}: {error}", data.scene_path),
+            }
+        });
+    } else {
+        // There's no file system on the web, the actions exist so the keymap stays the same everywhere
+        for name in ["save", "export", "load", "save_scene", "load_scene"] {
+            input.add_action(name, |_data : &mut InputData, _world : &mut World| {});
+        }
     }
 
     // Debug
-    input.bind_key(KeyCode::P, InputTrigger::Pressed, |_data : &mut InputData| {
-        dbg!(GRAPH.read().nodes.internal_memory());
+    input.add_action("dump_nodes", |_data : &mut InputData, world : &mut World| {
+        dbg!(world.graph.nodes.internal_memory());
+    });
+    input.add_action("compact", |_data : &mut InputData, world : &mut World| {
+        world.entities.compact_graph(&mut world.graph);
     });
-    input.bind_key(KeyCode::O, InputTrigger::Pressed, |data : &mut InputData| {
+    input.add_action("toggle_debug", |data : &mut InputData, _world : &mut World| {
         data.render_debug = !data.render_debug;
     });
-    input.bind_key(KeyCode::I, InputTrigger::Pressed, |data : &mut InputData| {
+    input.add_action("toggle_rotated", |data : &mut InputData, _world : &mut World| {
         data.render_rotated = !data.render_rotated;
     });
 
     // Camera Controls
-    input.bind_key(KeyCode::Equal, InputTrigger::Down, |_data : &mut InputData| {
-        CAMERA.write().change_zoom(1.02);
+    input.add_action("zoom_in", |_data : &mut InputData, world : &mut World| {
+        world.camera.change_zoom(1.02);
     });
-    input.bind_key(KeyCode::Minus, InputTrigger::Down, |_data : &mut InputData| {
-        CAMERA.write().change_zoom(1./1.02);
+    input.add_action("zoom_out", |_data : &mut InputData, world : &mut World| {
+        world.camera.change_zoom(1./1.02);
+    });
+    input.add_axis_action("zoom", |_data : &mut InputData, world : &mut World, scrolled : f32| {
+        world.camera.change_zoom(1.1_f32.powf(scrolled));
     });
 
+    input.load_keymap(DEFAULT_KEYMAP).unwrap();
     input
 }
+
+#[test]
+fn snapshots_keep_materials_history_and_contacts() {
+    use engine::camera::NoOpSink;
+    let mut world = World::default();
+    world.camera.set_sink(Box::new(NoOpSink));
+    let mut vars = InputData::default();
+    let spawn = |world:&mut World, x:f32, block:Leaf| {
+        let mut entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::new(x, 0.), 0);
+        let root = world.graph.get_root(block, 0);
+        entity.set_root(&mut world.graph, &world.blocks, root);
+        world.entities.spawn(entity)
+    };
+    let wall = spawn(&mut world, 1.5, Leaf(3));
+    world.entities.get_mut_entity(wall).unwrap().kinematic = true;
+    vars.target_id = spawn(&mut world, 0., Leaf(1));
+    // Repainted in a new material, so the snapshot has something to undo and a leaf a fresh palette doesn't have
+    vars.edit_color = add_material(&mut world, CollisionType::Solid);
+    let new_cell = vars.edit_cell(&world.graph);
+    let entity = world.entities.get_mut_entity(vars.target_id).unwrap();
+    entity.edit_root(&mut world.graph, &world.blocks, |_, _| Ok(new_cell)).unwrap();
+    entity.velocity.x = 1.;
+    n_body_collisions(&mut world);
+    assert!(!world.contacts.manifolds().is_empty());
+    let snapshot = vars.snapshot(&world);
+
+    let mut replay = World::default();
+    let mut replay_vars = InputData::default();
+    replay_vars.restore(&mut replay, &snapshot).unwrap();
+    assert_eq!(replay.blocks.leaf_count(), world.blocks.leaf_count());
+    assert_eq!(replay_vars.edit_color, vars.edit_color);
+    let impulses = |world:&World| world.contacts.manifolds().iter().flat_map(|manifold| &manifold.points).map(|contact| contact.normal_impulse).collect::<Vec<_>>();
+    assert_eq!(impulses(&replay), impulses(&world));
+    let restored = replay.entities.get_mut_entity(replay_vars.target_id).unwrap();
+    assert_eq!(replay.graph.leaf(restored.location.pointer.pointer), Some(vars.edit_color));
+    restored.undo(&mut replay.graph, &replay.blocks);
+    assert_eq!(replay.graph.leaf(restored.location.pointer.pointer), Some(Leaf(1)));
+}
+
+#[test]
+fn strokes_undo_at_once_on_the_entity_they_started_on() {
+    use macroquad::input::MouseButton;
+    use engine::input::{InputEvent, InputType, InputTrigger, Modifiers};
+    let mut world = World::default();
+    let mut input = set_key_binds();
+    let mut vars = InputData::default();
+    let blank = |world:&mut World| {
+        let entity = Entity::blank(&mut world.graph, &world.blocks, Vec2::ZERO, 2);
+        world.entities.spawn(entity)
+    };
+    let (painted, other) = (blank(&mut world), blank(&mut world));
+    vars.target_id = painted;
+    vars.edit_color = Leaf(1);
+    let left = InputType::Mouse(MouseButton::Left);
+    for (cursor, triggers) in [(Vec2::splat(-1.5), &[InputTrigger::Pressed, InputTrigger::Down][..]), (Vec2::splat(1.5), &[InputTrigger::Down]), (Vec2::splat(1.5), &[InputTrigger::Released])] {
+        for &trigger in triggers { input.inject(InputEvent::new(left, trigger, Modifiers::NONE)) }
+        vars.cursor = cursor;
+        input.handle(&mut vars, &mut world);
+        // Switching target partway doesn't move the stroke
+        vars.target_id = other;
+    }
+    assert_eq!(world.entities.get_entity(painted).unwrap().mass.mass, 2.);
+    assert_eq!(world.entities.get_entity(other).unwrap().mass.mass, 0.);
+    let entity = world.entities.get_mut_entity(painted).unwrap();
+    assert!(entity.undo(&mut world.graph, &world.blocks));
+    assert_eq!(entity.mass.mass, 0.);
+}